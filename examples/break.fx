@@ -0,0 +1,21 @@
+var i = 0;
+while (true) {
+    i = i + 1;
+    if (i == 3) {
+        continue;
+    }
+    if (i > 5) {
+        break;
+    }
+    print i;
+}
+
+var picked = [];
+for (var n = 0; n < 10; n = n + 1) {
+    if (n == 1 or n == 3) continue;
+    {
+        if (n == 7) break;
+    }
+    push(picked, n);
+}
+print picked;
@@ -0,0 +1,52 @@
+fn divide(a, b) -> {
+    if (b == 0) {
+        throw "division by zero";
+    }
+    return a / b;
+}
+
+try {
+    print divide(10, 2);
+    print divide(1, 0);
+    print "unreachable";
+} catch (e) {
+    print "caught: " + e;
+} finally {
+    print "cleanup";
+}
+
+try {
+    var n = nil;
+    print n + 1;
+} catch (e) {
+    print e.message;
+    print e.line;
+}
+
+fn withFinally() -> {
+    try {
+        return "from try";
+    } finally {
+        print "finally runs before return";
+    }
+}
+print withFinally();
+
+for (var i = 0; i < 3; i = i + 1) {
+    try {
+        if (i == 1) continue;
+        print i;
+    } finally {
+        print "after " + "iteration";
+    }
+}
+
+try {
+    try {
+        throw {"code": 42};
+    } finally {
+        print "inner finally";
+    }
+} catch (e) {
+    print e["code"];
+}
@@ -0,0 +1,15 @@
+import "lib/geometry.fx" as geo;
+import "lib/shapes.fx" as shapes;
+import "lib/geometry.fx" as again;
+
+print geo.pi;
+print geo.square(4);
+print shapes.Square(3).area();
+print geo.count;
+print again.count;
+
+try {
+    geo.hidden();
+} catch (e) {
+    print e.message;
+}
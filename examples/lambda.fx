@@ -0,0 +1,46 @@
+var double = fn (x) -> x * 2;
+print double(21);
+
+fn apply(f, xs) -> {
+    var out = [];
+    var i = 0;
+    while (i < len(xs)) {
+        push(out, f(xs[i]));
+        i = i + 1;
+    }
+    return out;
+}
+print apply(fn (x) -> x + 1, [1, 2, 3]);
+
+fn counter() -> {
+    var count = 0;
+    return fn () -> {
+        count = count + 1;
+        return count;
+    };
+}
+var next = counter();
+next();
+print next();
+
+class Button -> {
+    init(label) -> {
+        this.label = label;
+    }
+
+    onClick() -> {
+        return fn () -> "clicked " + this.label;
+    }
+}
+print Button("ok").onClick()();
+print fn (a, b) -> a;
+
+{
+    var countdown = fn (n) -> {
+        if (n > 0) {
+            return countdown(n - 1);
+        }
+        return "liftoff";
+    };
+    print countdown(3);
+}
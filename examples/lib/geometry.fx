@@ -0,0 +1,13 @@
+export var pi = 3.14159;
+export var count = 0;
+
+export fn square(x) -> {
+    count = count + 1;
+    return x * x;
+}
+
+fn hidden() -> {
+    return "not exported";
+}
+
+print "geometry loaded";
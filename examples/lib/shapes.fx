@@ -0,0 +1,11 @@
+import "geometry.fx" as geometry;
+
+export class Square -> {
+    init(side) -> {
+        this.side = side;
+    }
+
+    area() -> {
+        return geometry.square(this.side);
+    }
+}
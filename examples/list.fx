@@ -0,0 +1,19 @@
+var xs = [1, 2, 3];
+print xs;
+print xs[0] + xs[2];
+
+xs[1] = "two";
+print xs;
+
+push(xs, 4);
+print len(xs);
+print pop(xs);
+print slice(xs, 1, 3);
+
+var grid = [[1, 2], [3, 4]];
+grid[1][0] = 30;
+print grid;
+
+var alias = xs;
+push(alias, nil);
+print xs;
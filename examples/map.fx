@@ -0,0 +1,29 @@
+var ages = {"ada": 36, "alan": 41};
+print ages;
+print ages["ada"];
+
+ages["grace"] = 85;
+ages["alan"] = 42;
+print len(ages);
+print keys(ages);
+print values(ages);
+
+print has(ages, "ada");
+print remove(ages, "ada");
+print has(ages, "ada");
+
+var mixed = {1: "one", true: "yes", "k": [1, 2]};
+print mixed[1] + " " + mixed[true];
+print mixed;
+
+var counts = {};
+var words = ["a", "b", "a"];
+while (len(words) > 0) {
+    var w = pop(words);
+    if (has(counts, w)) {
+        counts[w] = counts[w] + 1;
+    } else {
+        counts[w] = 1;
+    }
+}
+print counts;
@@ -0,0 +1,36 @@
+var xs = [5, 3, 8, 1];
+
+print map(xs, fn(x) -> x * 2);
+print filter(xs, fn(x) -> x > 2);
+print sort(xs);
+print sort(xs, fn(a, b) -> b - a);
+print xs;
+
+print push(xs, 9, 10, 11);
+print sort(["pear", "apple", "fig"]);
+
+class Counter -> {
+    init() -> {
+        this.calls = 0;
+    }
+    bump(x) -> {
+        this.calls = this.calls + 1;
+        return x + 1;
+    }
+}
+
+var counter = Counter();
+print map([1, 2, 3], counter.bump);
+print counter.calls;
+
+try {
+    sort([1, "a"]);
+} catch (e) {
+    print e.message;
+}
+
+try {
+    map([1, 2], fn(x) -> x + nil);
+} catch (e) {
+    print e.message;
+}
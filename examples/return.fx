@@ -0,0 +1,24 @@
+fn find(xs, target) -> {
+    var i = 0;
+    while (i < len(xs)) {
+        {
+            if (xs[i] == target) {
+                return i;
+            }
+        }
+        i = i + 1;
+    }
+    return -1;
+}
+print find([4, 5, 6], 6);
+print find([4, 5, 6], 7);
+
+fn firstNonZero(xs) -> {
+    for (var i = 0; i < len(xs); i = i + 1) {
+        if (xs[i] == 0) continue;
+        {
+            return xs[i];
+        }
+    }
+}
+print firstNonZero([0, 3, 8]);
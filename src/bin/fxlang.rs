@@ -1,40 +1,36 @@
 use std::{
-    env, fs, io::{self, Write}, process,
+    env, io::{self, Write}, thread,
 };
-use fxlang::frontend::lexer::Lexer;
 use colored::*;
-use fxlang::frontend::parser::Parser;
-use fxlang::frontend::expr::AstPrinter;
-use fxlang::frontend::interpreter::Interpreter;
+use fxlang::{Backend, Engine};
+use fxlang::frontend::capability::Capabilities;
 use fxlang::frontend::error::Error;
-use std::io::stdin;
+use fxlang::frontend::interpreter::Interpreter;
 use std::process::exit;
-use fxlang::frontend::resolver::Resolver;
+
+//sysexits(3) statuses, so a caller can tell a broken script from a failing one
+const EX_USAGE: i32 = 64;
+const EX_DATAERR: i32 = 65;
+const EX_SOFTWARE: i32 = 70;
+const EX_IOERR: i32 = 74;
 
 struct FxLang{
-    interpreter: Interpreter,
+    engine: Engine,
 }
 
 impl FxLang {
     fn new() -> Self {
-        FxLang {
-            interpreter:Interpreter::new()
-        }
+        //Scripts run from the command line are trusted with everything
+        let mut engine = Engine::with_interpreter(Interpreter::builder().capabilities(Capabilities::all()).build());
+        engine.set_max_stack(STACK_SIZE / 2);
+        FxLang { engine }
     }
-//TODO look into return error
+
     fn run_file(&mut self, path: &str) {
-        //Read the file .fx
-        let input = fs::read_to_string(path);
-        /*
-            Result<T,E> -> https://doc.rust-lang.org/std/result/
-            match ~ switch in C
-        */
-        match input {
-            Ok(bytes) => self.run(bytes),
-            Err(e) => {
-                eprintln!("Failed to read file {:?}", e);
-                process::exit(74);
-            }
+        //Read and run the file .fx, imports resolve relative to it
+        let result = self.engine.eval_file(path);
+        if let Err(e) = result {
+            exit(self.report(e));
         }
     }
 
@@ -44,57 +40,77 @@ impl FxLang {
             print!("> ");
             io::stdout().flush().expect("Failed to flush stdout!");
             let mut input = String::new();
-            io::stdin()
+            //Same reader readNum/readString use, so neither swallows the other's lines
+            let read = self.engine.interpreter().input()
                 .read_line(&mut input)
                 .expect("Failed to read from stdin!");
+            if read == 0 {
+                break;
+            }
 
             self.run(input);
         }
     }
 
     fn run(&mut self,src: String) {
-        /*
-            &str is fixed length and String is growable
-        */
-        let mut lexer = Lexer::new(src);
-        let tokens = lexer.scan_tokens();
-        let mut parser = Parser::new(tokens.to_vec());
-        let statements = match parser.parse() {
-            Ok(res) => res,
+        //The REPL carries on after errors, only exit() ends it
+        match self.engine.eval_named("<repl>", &src) {
+            Err(Error::Exit(status)) => exit(status),
             Err(e) => {
-                eprintln!("Parsing Error: {:?}",e);
-                process::exit(74)
-            }
-        };
-        let mut resolver  = Resolver::new(&mut self.interpreter);
-        resolver.resolve_stmts(&statements);
-        if resolver.had_error{
-            return ()
+                self.report(e);
+            },
+            Ok(_) => ()
         }
+    }
 
-        match self.interpreter.interpret(&statements){
-            Ok(_)=> (),
-            Err(e) => eprintln!("{}",e)
+    /*
+        Result<T,E> -> https://doc.rust-lang.org/std/result/
+        match ~ switch in C
+    */
+    //Prints the error and gives the status the process should exit with
+    fn report(&self, error: Error) -> i32 {
+        match error {
+            Error::Io(e) => {
+                eprintln!("Failed to read file {:?}", e);
+                EX_IOERR
+            },
+            Error::Exit(status) => status,
+            e @ (Error::Lex(_) | Error::Parse(_) | Error::Resolve(_) | Error::Compile(_)) => {
+                eprint!("{}", self.engine.render(&e));
+                EX_DATAERR
+            },
+            e => {
+                eprint!("{}", self.engine.render(&e));
+                EX_SOFTWARE
+            }
         }
-        // let mut printer = AstPrinter;
-        // match printer.print(expr) {
-        //     Ok(res) => println!("{}", res),
-        //     Err(e) => eprintln!("Error Printing AST: {:?}", e)
-        // }
     }
 }
 
+//fx calls recurse on the Rust stack, this leaves room for the default max call depth in debug builds
+const STACK_SIZE: usize = 256 * 1024 * 1024;
+
 fn main() -> Result<(), Error> {
     let args: Vec<String> = env::args().collect();
-    let mut fxlang = FxLang::new();
-    match args.as_slice() {
-        [_, file] => fxlang.run_file(file),
-        [_] => fxlang.run_repl(),
-        _ => {
-            eprintln!("Usage: fxlang [script]");
-            exit(64)
-        }
-    }
+    let cli = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || {
+            let mut fxlang = FxLang::new();
+            //--vm runs on the bytecode backend instead of the tree-walker
+            let mut args: Vec<&str> = args.iter().map(String::as_str).collect();
+            if let Some(at) = args.iter().position(|arg| *arg == "--vm") {
+                args.remove(at);
+                fxlang.engine.set_backend(Backend::Vm);
+            }
+            match args.as_slice() {
+                [_, file] => fxlang.run_file(file),
+                [_] => fxlang.run_repl(),
+                _ => {
+                    eprintln!("Usage: fxlang [--vm] [script]");
+                    exit(EX_USAGE)
+                }
+            }
+        })?;
+    cli.join().expect("Interpreter thread panicked");
     Ok(())
 }
-
@@ -0,0 +1,449 @@
+use crate::frontend::error::Error;
+use crate::frontend::convert::TypedNative;
+use crate::frontend::fxclass::FxClass;
+use crate::frontend::fxfx::FxFx;
+use crate::frontend::fxunit::FxUnit;
+use crate::frontend::interpreter::Interpreter;
+use crate::frontend::stmt::Stmt;
+use crate::frontend::trace::Traceback;
+use crate::vm;
+use std::cell::RefCell;
+use std::fs;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::path::Path;
+use std::time::Duration;
+
+/*
+    Embedding entry point. Runs source through lexer -> parser -> resolver -> interpreter
+    and hands every failure back as an Error, globals persist between calls.
+*/
+pub struct Engine {
+    interpreter: Interpreter,
+    backend: Backend,
+}
+
+//What runs the resolved program, both share globals, natives and modules
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    TreeWalker,
+    Vm,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Engine::with_interpreter(Interpreter::new())
+    }
+
+    //An engine whose print and readNum/readString use the given streams instead of stdio
+    pub fn with_io(output: Box<dyn Write>, input: Box<dyn BufRead>) -> Self {
+        Engine::with_interpreter(Interpreter::with_io(output, input))
+    }
+
+    //An engine around a configured interpreter, e.g. one from Interpreter::builder
+    pub fn with_interpreter(interpreter: Interpreter) -> Self {
+        Engine { interpreter, backend: Backend::TreeWalker }
+    }
+
+    //Code evaluated from here on runs on this backend
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.backend = backend;
+    }
+
+    //Runs the source, the value is that of a trailing expression statement or nil
+    pub fn eval(&mut self, src: &str) -> Result<FxUnit, Error> {
+        self.eval_named("<script>", src)
+    }
+
+    //Like eval, with `name` standing for the source in diagnostics
+    pub fn eval_named(&mut self, name: &str, src: &str) -> Result<FxUnit, Error> {
+        self.interpreter.clear_traceback();
+        self.interpreter.start_run();
+        let mut statements = self.interpreter.load(name, src.to_string())?;
+        if self.backend == Backend::Vm {
+            return vm::run(&mut self.interpreter, &statements);
+        }
+        let last = match statements.last() {
+            Some(Stmt::Expression { .. }) => statements.pop(),
+            _ => None
+        };
+        self.interpreter.interpret(&statements)?;
+        match last {
+            //Counted as a statement, as it is when the VM runs it or a module ends with it
+            Some(Stmt::Expression { expr, .. }) => self.interpreter.tick().and_then(|_| self.interpreter.evaluate(&expr)).inspect_err(|e| {
+                self.interpreter.note_traceback(e, None);
+            }),
+            _ => Ok(FxUnit::Nil)
+        }
+    }
+
+    //Like eval, with imports resolved relative to the file
+    pub fn eval_file<P: AsRef<Path>>(&mut self, path: P) -> Result<FxUnit, Error> {
+        let path = path.as_ref();
+        let src = fs::read_to_string(path)?;
+        self.interpreter.set_script_path(path);
+        self.eval_named(&path.display().to_string(), &src)
+    }
+
+    pub fn get(&self, name: &str) -> Option<FxUnit> {
+        self.interpreter.globals.borrow().lookup(name)
+    }
+
+    //Defines the global, replacing any previous value
+    pub fn set(&mut self, name: &str, value: FxUnit) {
+        self.interpreter.globals.borrow_mut().define(name.to_string(), value);
+    }
+
+    //Defines a global native from a typed Rust fn, e.g. `engine.register("add", |a: f64, b: f64| a + b)`
+    pub fn register<F, Args>(&mut self, name: &str, f: F)
+        where F: TypedNative<Args> + 'static {
+        self.set(name, FxUnit::Callable(FxFx::typed(name, f)));
+    }
+
+    //Defines a class, usually a NativeClass, under its own name
+    pub fn register_class(&mut self, class: Rc<RefCell<FxClass>>) {
+        let name = class.borrow().name.clone();
+        self.set(&name, FxUnit::Class(class));
+    }
+
+    //Calls a global function or class by name
+    pub fn call(&mut self, name: &str, args: &[FxUnit]) -> Result<FxUnit, Error> {
+        self.interpreter.clear_traceback();
+        self.interpreter.start_run();
+        match self.get(name) {
+            Some(callee) => self.interpreter.call_from_host(name, &callee, args),
+            None => Err(Error::Native { message: format!("Undefined variable '{}'.", name) })
+        }
+    }
+
+    //The error as source snippets with carets, plus the traceback of a runtime error
+    pub fn render(&self, error: &Error) -> String {
+        let mut rendered = error.render(self.interpreter.sources());
+        if let (Some(_), Some(traceback)) = (error.line(), self.traceback()) {
+            rendered.push_str(&traceback.to_string());
+        }
+        rendered
+    }
+
+    //Call stack of the last uncaught runtime error from eval or call
+    pub fn traceback(&self) -> Option<&Traceback> {
+        self.interpreter.traceback()
+    }
+
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.interpreter.set_max_call_depth(depth);
+    }
+
+    //Past this many bytes of Rust stack a call fails like one past the max depth
+    pub fn set_max_stack(&mut self, bytes: usize) {
+        self.interpreter.set_max_stack(bytes);
+    }
+
+    //Limits for each eval or call, exceeding one gives Error::Aborted. Both backends count the statements started
+    pub fn set_step_budget(&mut self, steps: Option<u64>) {
+        self.interpreter.set_step_budget(steps);
+    }
+
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.interpreter.set_timeout(timeout);
+    }
+
+    pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+        self.interpreter.cancel_flag()
+    }
+
+    pub fn interpreter(&mut self) -> &mut Interpreter {
+        &mut self.interpreter
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::convert::FromFx;
+    use crate::frontend::fxclass::NativeClass;
+    use crate::frontend::fxfx::Arity;
+    use crate::frontend::error::Abort;
+    use crate::frontend::capability::Capability;
+
+    #[test]
+    fn eval_keeps_globals() {
+        let mut engine = Engine::new();
+        engine.eval("var a = 20;").unwrap();
+        match engine.eval("a + 22;").unwrap() {
+            FxUnit::Number(n) => assert_eq!(n, 42.0),
+            other => panic!("unexpected {}", other)
+        }
+    }
+
+    #[test]
+    fn call_by_name() {
+        let mut engine = Engine::new();
+        engine.set("base", FxUnit::Number(10.0));
+        engine.eval("fn add(x) -> { return base + x; }").unwrap();
+        match engine.call("add", &[FxUnit::Number(5.0)]).unwrap() {
+            FxUnit::Number(n) => assert_eq!(n, 15.0),
+            other => panic!("unexpected {}", other)
+        }
+        assert!(engine.call("missing", &[]).is_err());
+    }
+
+    #[test]
+    fn typed_natives() {
+        let mut engine = Engine::new();
+        engine.register("scale", |xs: Vec<f64>, k: f64| xs.into_iter().map(|x| x * k).collect::<Vec<f64>>());
+        engine.register("greet", |name: Option<String>| format!("hi {}", name.unwrap_or_else(|| "there".to_string())));
+        let scaled = engine.eval("scale([1, 2], 3);").unwrap();
+        assert_eq!(Vec::<i32>::from_fx(&scaled), Ok(vec![3, 6]));
+        assert_eq!(String::from_fx(&engine.eval("greet(nil);").unwrap()), Ok("hi there".to_string()));
+        match engine.eval("scale(1, 2);") {
+            Err(Error::Runtime { message, .. }) => assert_eq!(message, "Expected list but found number."),
+            other => panic!("unexpected {:?}", other)
+        }
+    }
+
+    struct Tally {
+        total: f64,
+    }
+
+    fn tally_engine() -> Engine {
+        let mut engine = Engine::new();
+        engine.register_class(NativeClass::<Tally>::new("Tally")
+            .init(Arity::Range(0, 1), |_, args| {
+                let total = match args.first() {
+                    Some(start) => f64::from_fx(start)?,
+                    None => 0.0
+                };
+                Ok(Tally { total })
+            })
+            .method("add", Arity::Exact(1), |_, tally, args| {
+                tally.total += f64::from_fx(&args[0])?;
+                Ok(FxUnit::Number(tally.total))
+            })
+            .build());
+        engine
+    }
+
+    #[test]
+    fn native_classes() {
+        let mut engine = tally_engine();
+        let total = engine.eval("var t = Tally(5); t.add(2); t.add(3);").unwrap();
+        assert_eq!(f64::from_fx(&total), Ok(10.0));
+
+        let src = "class Doubler < Tally -> {
+            init(start) -> { super.init(start * 2); this.label = \"doubled\"; }
+            add(x) -> { return super.add(x * 2); }
+        }
+        var d = Doubler(1);
+        d.add(4);";
+        assert_eq!(f64::from_fx(&engine.eval(src).unwrap()), Ok(10.0));
+        assert_eq!(String::from_fx(&engine.eval("d.label;").unwrap()), Ok("doubled".to_string()));
+
+        let src = "class Broken < Tally -> { init() -> {} }
+        Broken().add(1);";
+        match engine.eval(src) {
+            Err(Error::Runtime { message, .. }) => assert_eq!(message, "Tally.add called on an instance without a Tally value."),
+            other => panic!("unexpected {:?}", other)
+        }
+    }
+
+    #[test]
+    fn traceback_of_uncaught_error() {
+        let mut engine = Engine::new();
+        let src = "fn inner(x) -> {\n  return x + nil;\n}\nfn outer() -> {\n  return inner(1);\n}\nouter();";
+        assert!(engine.eval(src).is_err());
+        assert_eq!(engine.traceback().unwrap().to_string(), "at inner (line 2)\nat outer (line 5)\nat <script> (line 7)\n");
+
+        assert!(engine.call("outer", &[]).is_err());
+        assert_eq!(engine.traceback().unwrap().to_string(), "at inner (line 2)\nat outer (line 5)\nat <host>\n");
+
+        engine.eval("try { outer(); } catch (e) {}").unwrap();
+        assert!(engine.traceback().is_none());
+    }
+
+    #[test]
+    fn call_depth_limit() {
+        let mut engine = Engine::new();
+        engine.set_max_call_depth(32);
+        let src = "fn down(n) -> { return down(n + 1); }
+        var message;
+        try { down(0); } catch (e) { message = e.message; }
+        message;";
+        let message = engine.eval(src).unwrap();
+        assert_eq!(message.to_string(), "Stack overflow: call depth exceeded 32 calling 'down'.");
+
+        assert!(engine.eval("down(0);").is_err());
+        let traceback = engine.traceback().unwrap().to_string();
+        assert!(traceback.starts_with("at down (line 1)\nat down (line 1)\nat down (line 1)\n... previous line repeated 29 more times\n"));
+    }
+
+    #[test]
+    fn default_limits_fit_test_thread() {
+        //Test threads get a 2MB stack, deeper bodies use more of it per call
+        let src = "fn down(n) -> { if (true) { while (true) { return 1 + down(n + 1); } } }
+        var message;
+        try { down(0); } catch (e) { message = e.message; }
+        message;";
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let mut engine = Engine::new();
+            engine.set_backend(backend);
+            let message = engine.eval(src).unwrap().to_string();
+            assert!(message.starts_with("Stack overflow: "), "{}", message);
+            assert!(engine.eval("down(0);").is_err());
+        }
+    }
+
+    #[test]
+    fn host_limits_abort() {
+        let mut engine = Engine::new();
+        engine.set_step_budget(Some(1000));
+        let looping = "try { while (true) {} } catch (e) { print \"caught\"; }";
+        assert!(matches!(engine.eval(looping), Err(Error::Aborted(Abort::StepBudget(1000)))));
+        //The budget is per run
+        assert!(engine.eval("var i = 0; while (i < 100) i = i + 1;").is_ok());
+
+        engine.set_step_budget(None);
+        engine.set_timeout(Some(Duration::from_millis(20)));
+        assert!(matches!(engine.eval(looping), Err(Error::Aborted(Abort::Deadline(_)))));
+
+        engine.set_timeout(None);
+        let flag = engine.cancel_flag();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            flag.store(true, std::sync::atomic::Ordering::Relaxed);
+        });
+        assert!(matches!(engine.eval(looping), Err(Error::Aborted(Abort::Cancelled))));
+        canceller.join().unwrap();
+    }
+
+    #[test]
+    fn capabilities_gate_natives() {
+        let mut engine = Engine::with_interpreter(Interpreter::builder().allow(Capability::Env).build());
+        assert!(matches!(engine.eval("getEnv(\"FXLANG_SURELY_UNSET\");"), Ok(FxUnit::Nil)));
+        assert!(matches!(engine.eval("len([1]);"), Ok(FxUnit::Number(_))));
+        match engine.eval("clock();") {
+            Err(Error::Runtime { message, .. }) => assert_eq!(message, "clock() needs the 'time' capability, which this interpreter was not given."),
+            other => panic!("unexpected {:?}", other)
+        }
+        assert!(engine.eval("readFile(\"Cargo.toml\");").is_err());
+
+        let mut default = Engine::new();
+        assert!(default.eval("clock();").is_ok());
+        assert!(default.eval("exec(\"true\");").is_err());
+    }
+
+    #[test]
+    fn same_line_resolution() {
+        let mut engine = Engine::new();
+        let seen = engine.eval("var a = \"g\"; var seen = [a]; { var a = 1; push(seen, a); a = a + 1; push(seen, a); } seen;").unwrap();
+        assert_eq!(format!("{}", seen), "[\"g\", 1, 2]");
+        let sum = engine.eval("var sum = 0; for (var i = 0; i < 4; i = i + 1) { var i2 = i * i; sum = sum + i2; } sum;").unwrap();
+        assert_eq!(format!("{}", sum), "14");
+    }
+
+    #[test]
+    fn backends_agree() {
+        let src = r#"
+            fn counter() -> { var n = 0; return fn() -> { n = n + 1; return n; }; }
+            var c = counter(); c(); c();
+            var seen = 0;
+            for (var i = 0; i < 5; i = i + 1) {
+                try { if (i == 1) continue; if (i == 3) break; } finally { seen = seen + 1; }
+            }
+            fn fib(n) -> { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+            var down;
+            { var f = fn (n) -> { if (n > 0) { return f(n - 1); } return 3; }; down = f(3); }
+            [c(), seen, fib(15), down];
+        "#;
+        let run = |backend| {
+            let mut engine = Engine::new();
+            engine.set_backend(backend);
+            format!("{}", engine.eval(src).unwrap())
+        };
+        assert_eq!(run(Backend::TreeWalker), run(Backend::Vm));
+        assert_eq!(run(Backend::Vm), "[3, 4, 610, 3]");
+    }
+
+    #[test]
+    fn backends_count_the_same_steps() {
+        let src = r#"
+            class Box -> { init(v) -> { this.v = v; } get() -> { return this.v; } }
+            fn sum(n) -> { var total = 0; while (n > 0) { total = total + Box(n).get(); n = n - 1; } return total; }
+            var seen = 0;
+            for (var i = 0; i < 4; i = i + 1) {
+                try { if (i == 2) continue; throw i; } catch (e) { seen = seen + e; } finally { seen = seen + 1; }
+            }
+            { var inner = fn () -> { return sum(3); }; seen = seen + inner(); }
+            seen;
+        "#;
+        let steps = |backend, budget| {
+            let mut engine = Engine::new();
+            engine.set_backend(backend);
+            engine.set_step_budget(budget);
+            let result = engine.eval(src).map(|value| value.to_string());
+            (result, engine.interpreter().steps())
+        };
+        let (result, total) = steps(Backend::TreeWalker, None);
+        assert_eq!(result.unwrap(), "14");
+        assert_eq!(steps(Backend::Vm, None).1, total);
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            assert!(steps(backend, Some(total)).0.is_ok());
+            assert!(matches!(steps(backend, Some(total - 1)).0, Err(Error::Aborted(Abort::StepBudget(_)))));
+        }
+    }
+
+    #[test]
+    fn sort_survives_any_comparator() {
+        let src = r#"
+            var xs = [];
+            for (var i = 0; i < 200; i = i + 1) push(xs, i);
+            var t = false;
+            var shuffled = sort(xs, fn (a, b) -> { t = !t; if (t) return -1; return 1; });
+            var sum = 0;
+            for (var i = 0; i < len(shuffled); i = i + 1) sum = sum + shuffled[i];
+            var calls = 0;
+            var message;
+            try {
+                sort(xs, fn (a, b) -> { calls = calls + 1; if (calls == 3) throw "bad compare"; return a - b; });
+            } catch (e) { message = e; }
+            [len(shuffled), sum, calls, message, sort([3, 1, 2], fn (a, b) -> a - b)];
+        "#;
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let mut engine = Engine::new();
+            engine.set_backend(backend);
+            assert_eq!(engine.eval(src).unwrap().to_string(), "[200, 19900, 3, \"bad compare\", [1, 2, 3]]");
+        }
+    }
+
+    #[test]
+    fn exit_unwinds() {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let mut engine = Engine::with_interpreter(Interpreter::builder().allow(Capability::Process).build());
+            engine.set_backend(backend);
+            let src = "var done = false; fn quit() -> { try { exit(3); } catch (e) { return 1; } finally { done = true; } } quit();";
+            assert!(matches!(engine.eval(src), Err(Error::Exit(3))));
+            assert!(matches!(engine.get("done"), Some(FxUnit::Boolean(true))));
+            assert!(matches!(engine.eval("exit();"), Err(Error::Exit(0))));
+            assert!(matches!(engine.eval("exit(1.5);"), Err(Error::Runtime { .. })));
+        }
+        assert!(matches!(Engine::new().eval("exit(1);"), Err(Error::Runtime { .. })));
+    }
+
+    #[test]
+    fn errors_are_values() {
+        let mut engine = Engine::new();
+        assert!(matches!(engine.eval("var a = \"open;"), Err(Error::Lex(_))));
+        assert!(matches!(engine.eval("var = ;"), Err(Error::Parse(_))));
+        assert!(matches!(engine.eval("return 1;"), Err(Error::Resolve(_))));
+        assert!(matches!(engine.eval("1 + nil;"), Err(Error::Runtime { .. })));
+        assert!(matches!(engine.eval_file("does/not/exist.fx"), Err(Error::Io(_))));
+    }
+}
@@ -0,0 +1,68 @@
+use std::fmt;
+
+//A group of natives that reach outside the interpreter, the host decides which are installed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capability {
+    Io,
+    Time,
+    Fs,
+    Process,
+    Env,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 5] = [Capability::Io, Capability::Time, Capability::Fs, Capability::Process, Capability::Env];
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Capability::Io => "io",
+            Capability::Time => "time",
+            Capability::Fs => "fs",
+            Capability::Process => "process",
+            Capability::Env => "env",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/*
+    The set of capabilities an interpreter was built with. The default keeps what scripts
+    always had, console io and the clock, anything touching files, processes or the
+    environment has to be allowed explicitly.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Capabilities(u8);
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Capabilities::none().with(Capability::Io).with(Capability::Time)
+    }
+}
+
+impl Capabilities {
+    pub fn none() -> Self {
+        Capabilities(0)
+    }
+
+    pub fn all() -> Self {
+        Capability::ALL.iter().fold(Capabilities::none(), |caps, &cap| caps.with(cap))
+    }
+
+    pub fn with(self, capability: Capability) -> Self {
+        Capabilities(self.0 | capability.bit())
+    }
+
+    pub fn without(self, capability: Capability) -> Self {
+        Capabilities(self.0 & !capability.bit())
+    }
+
+    pub fn allows(&self, capability: Capability) -> bool {
+        self.0 & capability.bit() != 0
+    }
+}
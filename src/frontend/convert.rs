@@ -0,0 +1,302 @@
+use crate::frontend::fxunit::{FxUnit, MapKey};
+use crate::frontend::error::Error;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/*
+    Conversions between Rust values and FxUnit for embedders. IntoFx never fails,
+    FromFx reports what it wanted and what it got instead.
+*/
+pub trait IntoFx {
+    fn into_fx(self) -> FxUnit;
+}
+
+pub trait FromFx: Sized {
+    fn from_fx(unit: &FxUnit) -> Result<Self, ConvertError>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConvertError {
+    //The value is of another fx type, both named as FxUnit::type_name does
+    Mismatch { expected: &'static str, found: &'static str },
+    //A number with a fraction or outside the range of the integer type
+    OutOfRange { target: &'static str, value: f64 },
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConvertError::Mismatch { expected, found } => write!(f, "Expected {} but found {}.", expected, found),
+            ConvertError::OutOfRange { target, value } => write!(f, "{} does not fit in {}.", value, target),
+        }
+    }
+}
+
+impl std::error::Error for ConvertError {}
+
+//Inside a native a failed conversion is an ordinary runtime error at the call site
+impl From<ConvertError> for Error {
+    fn from(e: ConvertError) -> Self {
+        Error::Native { message: e.to_string() }
+    }
+}
+
+fn mismatch<T>(expected: &'static str, unit: &FxUnit) -> Result<T, ConvertError> {
+    Err(ConvertError::Mismatch { expected, found: unit.type_name() })
+}
+
+impl IntoFx for FxUnit {
+    fn into_fx(self) -> FxUnit {
+        self
+    }
+}
+
+impl FromFx for FxUnit {
+    fn from_fx(unit: &FxUnit) -> Result<Self, ConvertError> {
+        Ok(unit.clone())
+    }
+}
+
+impl IntoFx for () {
+    fn into_fx(self) -> FxUnit {
+        FxUnit::Nil
+    }
+}
+
+impl IntoFx for f64 {
+    fn into_fx(self) -> FxUnit {
+        FxUnit::Number(self)
+    }
+}
+
+impl FromFx for f64 {
+    fn from_fx(unit: &FxUnit) -> Result<Self, ConvertError> {
+        match unit {
+            FxUnit::Number(n) => Ok(*n),
+            other => mismatch("number", other)
+        }
+    }
+}
+
+impl IntoFx for f32 {
+    fn into_fx(self) -> FxUnit {
+        FxUnit::Number(self as f64)
+    }
+}
+
+impl FromFx for f32 {
+    fn from_fx(unit: &FxUnit) -> Result<Self, ConvertError> {
+        f64::from_fx(unit).map(|n| n as f32)
+    }
+}
+
+//fx only has f64, so integers round-trip through it and must be whole and in range
+macro_rules! integer_conversions {
+    ($($int:ty),*) => {$(
+        impl IntoFx for $int {
+            fn into_fx(self) -> FxUnit {
+                FxUnit::Number(self as f64)
+            }
+        }
+
+        impl FromFx for $int {
+            fn from_fx(unit: &FxUnit) -> Result<Self, ConvertError> {
+                let n = f64::from_fx(unit)?;
+                //MAX is 2^k - 1, which for 64 bit types rounds up to 2^k as an f64, so bound it
+                //strictly by MAX + 1, exact for every width, rather than letting `as` saturate
+                if n.fract() == 0.0 && n >= <$int>::MIN as f64 && n < <$int>::MAX as f64 + 1.0 {
+                    Ok(n as $int)
+                } else {
+                    Err(ConvertError::OutOfRange { target: stringify!($int), value: n })
+                }
+            }
+        }
+    )*};
+}
+
+integer_conversions!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl IntoFx for bool {
+    fn into_fx(self) -> FxUnit {
+        FxUnit::Boolean(self)
+    }
+}
+
+impl FromFx for bool {
+    fn from_fx(unit: &FxUnit) -> Result<Self, ConvertError> {
+        match unit {
+            FxUnit::Boolean(b) => Ok(*b),
+            other => mismatch("bool", other)
+        }
+    }
+}
+
+impl IntoFx for String {
+    fn into_fx(self) -> FxUnit {
+        FxUnit::String(self)
+    }
+}
+
+impl IntoFx for &str {
+    fn into_fx(self) -> FxUnit {
+        FxUnit::String(self.to_string())
+    }
+}
+
+impl FromFx for String {
+    fn from_fx(unit: &FxUnit) -> Result<Self, ConvertError> {
+        match unit {
+            FxUnit::String(s) => Ok(s.clone()),
+            other => mismatch("string", other)
+        }
+    }
+}
+
+impl<T: IntoFx> IntoFx for Option<T> {
+    fn into_fx(self) -> FxUnit {
+        match self {
+            Some(value) => value.into_fx(),
+            None => FxUnit::Nil
+        }
+    }
+}
+
+impl<T: FromFx> FromFx for Option<T> {
+    fn from_fx(unit: &FxUnit) -> Result<Self, ConvertError> {
+        match unit {
+            FxUnit::Nil => Ok(None),
+            other => T::from_fx(other).map(Some)
+        }
+    }
+}
+
+impl<T: IntoFx> IntoFx for Vec<T> {
+    fn into_fx(self) -> FxUnit {
+        FxUnit::list(self.into_iter().map(IntoFx::into_fx).collect())
+    }
+}
+
+impl<T: FromFx> FromFx for Vec<T> {
+    fn from_fx(unit: &FxUnit) -> Result<Self, ConvertError> {
+        match unit {
+            FxUnit::List(elements) => elements.borrow().iter().map(T::from_fx).collect(),
+            other => mismatch("list", other)
+        }
+    }
+}
+
+impl<T: IntoFx> IntoFx for HashMap<String, T> {
+    fn into_fx(self) -> FxUnit {
+        let entries: BTreeMap<MapKey, FxUnit> = self.into_iter()
+            .map(|(k, v)| (MapKey::String(k), v.into_fx()))
+            .collect();
+        FxUnit::map(entries)
+    }
+}
+
+impl<T: FromFx> FromFx for HashMap<String, T> {
+    fn from_fx(unit: &FxUnit) -> Result<Self, ConvertError> {
+        match unit {
+            FxUnit::Map(entries) => entries.borrow().iter().map(|(key, value)| match key {
+                MapKey::String(k) => Ok((k.clone(), T::from_fx(value)?)),
+                other => mismatch("string", &other.to_unit())
+            }).collect(),
+            other => mismatch("map", other)
+        }
+    }
+}
+
+//What a typed native may return, a plain value or a Result for natives that can fail
+pub trait NativeReturn {
+    fn into_result(self) -> Result<FxUnit, Error>;
+}
+
+impl<T: IntoFx> NativeReturn for T {
+    fn into_result(self) -> Result<FxUnit, Error> {
+        Ok(self.into_fx())
+    }
+}
+
+impl<T: IntoFx> NativeReturn for Result<T, Error> {
+    fn into_result(self) -> Result<FxUnit, Error> {
+        self.map(IntoFx::into_fx)
+    }
+}
+
+/*
+    A Rust fn with FromFx parameters, Args is the tuple of those parameter types.
+    FxFx::typed uses it to build a native that converts its arguments itself.
+*/
+pub trait TypedNative<Args> {
+    fn arity(&self) -> usize;
+    fn invoke(&self, args: &[FxUnit]) -> Result<FxUnit, Error>;
+}
+
+macro_rules! typed_native {
+    ($count:expr; $($arg:ident),*) => {
+        impl<F, R, $($arg),*> TypedNative<($($arg,)*)> for F
+            where F: Fn($($arg),*) -> R, R: NativeReturn, $($arg: FromFx),* {
+            fn arity(&self) -> usize {
+                $count
+            }
+
+            #[allow(unused_variables, unused_mut, non_snake_case)]
+            fn invoke(&self, args: &[FxUnit]) -> Result<FxUnit, Error> {
+                let mut args = args.iter();
+                $(let $arg = $arg::from_fx(args.next().unwrap_or(&FxUnit::Nil))?;)*
+                self($($arg),*).into_result()
+            }
+        }
+    };
+}
+
+typed_native!(0;);
+typed_native!(1; A);
+typed_native!(2; A, B);
+typed_native!(3; A, B, C);
+typed_native!(4; A, B, C, D);
+typed_native!(5; A, B, C, D, E);
+typed_native!(6; A, B, C, D, E, G);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        assert_eq!(f64::from_fx(&2.5.into_fx()), Ok(2.5));
+        assert_eq!(i32::from_fx(&(-7).into_fx()), Ok(-7));
+        assert_eq!(String::from_fx(&"fx".into_fx()), Ok("fx".to_string()));
+        assert_eq!(Option::<bool>::from_fx(&None::<bool>.into_fx()), Ok(None));
+        assert_eq!(Vec::<u8>::from_fx(&vec![1u8, 2, 3].into_fx()), Ok(vec![1, 2, 3]));
+
+        let mut scores = HashMap::new();
+        scores.insert("ansh".to_string(), 10i64);
+        assert_eq!(HashMap::<String, i64>::from_fx(&scores.clone().into_fx()), Ok(scores));
+    }
+
+    #[test]
+    fn typed_failures() {
+        assert_eq!(f64::from_fx(&FxUnit::Nil), Err(ConvertError::Mismatch { expected: "number", found: "nil" }));
+        assert_eq!(u8::from_fx(&FxUnit::Number(256.0)), Err(ConvertError::OutOfRange { target: "u8", value: 256.0 }));
+        assert_eq!(i32::from_fx(&FxUnit::Number(1.5)), Err(ConvertError::OutOfRange { target: "i32", value: 1.5 }));
+        assert!(Vec::<String>::from_fx(&vec![1.0].into_fx()).is_err());
+    }
+
+    #[test]
+    fn integer_bounds() {
+        let two_63 = 2f64.powi(63);
+        let two_64 = 2f64.powi(64);
+        assert_eq!(u8::from_fx(&FxUnit::Number(255.0)), Ok(255));
+        assert_eq!(i8::from_fx(&FxUnit::Number(-128.0)), Ok(-128));
+        assert!(i8::from_fx(&FxUnit::Number(128.0)).is_err());
+        assert_eq!(i64::from_fx(&FxUnit::Number(-two_63)), Ok(i64::MIN));
+        assert_eq!(i64::from_fx(&FxUnit::Number(two_63 - 1024.0)), Ok(i64::MAX - 1023));
+        assert_eq!(i64::from_fx(&FxUnit::Number(two_63)), Err(ConvertError::OutOfRange { target: "i64", value: two_63 }));
+        assert!(isize::from_fx(&FxUnit::Number(isize::MAX as f64)).is_err());
+        assert_eq!(u64::from_fx(&FxUnit::Number(two_64 - 2048.0)), Ok(u64::MAX - 2047));
+        assert_eq!(u64::from_fx(&FxUnit::Number(two_64)), Err(ConvertError::OutOfRange { target: "u64", value: two_64 }));
+        assert!(usize::from_fx(&FxUnit::Number(usize::MAX as f64)).is_err());
+        assert!(u32::from_fx(&FxUnit::Number(f64::INFINITY)).is_err());
+    }
+}
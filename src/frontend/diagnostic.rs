@@ -0,0 +1,156 @@
+use crate::frontend::error::Error;
+use crate::frontend::tokens::{Span, Token};
+use colored::*;
+use std::fmt::Write;
+
+//A source text the interpreter has loaded, spans point into it by index
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub name: String,
+    pub text: String,
+}
+
+impl Source {
+    //1-based line and char column of a byte offset, for a span's end it is the column just past it
+    pub fn position(&self, offset: usize) -> (usize, usize) {
+        let before = &self.text[..offset.min(self.text.len())];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    Lex,
+    Parse,
+    Resolve,
+    Compile,
+    Runtime,
+}
+
+impl Stage {
+    fn label(&self) -> &'static str {
+        match self {
+            Stage::Lex | Stage::Parse => "syntax error",
+            Stage::Resolve => "resolve error",
+            Stage::Compile => "compile error",
+            Stage::Runtime => "runtime error",
+        }
+    }
+}
+
+/*
+    One problem found in a program, from any stage. Spans are kept rather than rendered
+    text so the caller decides where it goes, render turns it into the report users see:
+
+        syntax error: Expect ';' after value
+          --> examples/calc.fx:3:8
+           |
+         3 | print a
+           |        ^
+*/
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub stage: Stage,
+    pub message: String,
+    //None for errors with no place in the source, like a failing native
+    pub span: Option<Span>,
+}
+
+impl Diagnostic {
+    pub fn new(stage: Stage, message: &str, span: Option<Span>) -> Self {
+        Diagnostic { stage, message: message.to_string(), span }
+    }
+
+    pub fn at(stage: Stage, token: &Token, message: &str) -> Self {
+        Diagnostic::new(stage, message, Some(token.span))
+    }
+
+    pub fn render(&self, sources: &[Source]) -> String {
+        let mut out = format!("{}: {}\n", self.stage.label().red().bold(), self.message.bold());
+        let (span, source) = match self.span.and_then(|span| sources.get(span.source).map(|source| (span, source))) {
+            Some(found) => found,
+            None => return out
+        };
+        let text = &source.text;
+        let start = span.start.min(text.len());
+        let end = span.end.clamp(start, text.len());
+        let (first, column) = source.position(start);
+        //A span ending with a newline ends on the line before it
+        let (last, _) = source.position(start + text[start..end].trim_end_matches('\n').len());
+        let width = last.to_string().len();
+        let gutter = " ".repeat(width + 1);
+
+        let _ = writeln!(out, "{}{} {}:{}:{}", " ".repeat(width), "-->".blue().bold(), source.name, first, column);
+        let _ = writeln!(out, "{}{}", gutter, "|".blue().bold());
+        let mut line_start = text[..start].rfind('\n').map_or(0, |i| i + 1);
+        for (line_no, line) in (first..=last).zip(text[line_start..].split('\n')) {
+            let line_end = line_start + line.len();
+            let _ = writeln!(out, "{:>width$} {} {}", line_no.to_string().blue().bold(), "|".blue().bold(), line, width = width);
+            //Underline the span's part of each line past its indentation, in chars not bytes
+            let from = if line_no == first { start } else { line_end - line.trim_start().len() };
+            let indent = text[line_start..from].chars().count();
+            let underline = text[from..end.clamp(from, line_end)].chars().count();
+            if underline > 0 || line_no == first {
+                let _ = writeln!(out, "{}{} {}{}", gutter, "|".blue().bold(), " ".repeat(indent), "^".repeat(underline.max(1)).red().bold());
+            }
+            line_start = line_end + 1;
+        }
+        out
+    }
+}
+
+impl Error {
+    //What to show for this error, compile errors carry one per problem found
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self {
+            Error::Lex(diagnostics) | Error::Parse(diagnostics) | Error::Resolve(diagnostics) | Error::Compile(diagnostics) => diagnostics.clone(),
+            Error::Runtime { token, message } => vec![Diagnostic::at(Stage::Runtime, token, message)],
+            Error::Throw { token, value } => vec![Diagnostic::at(Stage::Runtime, token, &format!("Uncaught {}", value))],
+            Error::Native { message } => vec![Diagnostic::new(Stage::Runtime, message, None)],
+            Error::Io(e) => vec![Diagnostic::new(Stage::Runtime, &e.to_string(), None)],
+            Error::Aborted(abort) => vec![Diagnostic::new(Stage::Runtime, &abort.to_string(), None)],
+            //Not a problem to report, the script asked to stop
+            Error::Exit(_) => Vec::new(),
+        }
+    }
+
+    pub fn render(&self, sources: &[Source]) -> String {
+        self.diagnostics().iter().map(|d| d.render(sources)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_snippet_and_caret() {
+        colored::control::set_override(false);
+        let sources = vec![Source { name: "calc.fx".to_string(), text: "var a = 1;\nprint a +;\n".to_string() }];
+        let span = Span { start: 19, end: 20, column: 9, source: 0 };
+        let rendered = Diagnostic::new(Stage::Parse, "Expect expression.", Some(span)).render(&sources);
+        assert_eq!(rendered, "syntax error: Expect expression.\n --> calc.fx:2:9\n  |\n2 | print a +;\n  |         ^\n");
+        let unplaced = Diagnostic::new(Stage::Runtime, "boom", None).render(&sources);
+        assert_eq!(unplaced, "runtime error: boom\n");
+
+        //Carets line up by chars when the line has multi-byte text before them
+        let sources = vec![Source { name: "u.fx".to_string(), text: "print \"é\" + ñ;".to_string() }];
+        let span = Span { start: 13, end: 15, column: 13, source: 0 };
+        let rendered = Diagnostic::new(Stage::Runtime, "Undefined variable 'ñ'.", Some(span)).render(&sources);
+        assert!(rendered.ends_with("1 | print \"é\" + ñ;\n  |             ^\n"), "{}", rendered);
+    }
+
+    #[test]
+    fn multi_line_spans_underline_each_line() {
+        colored::control::set_override(false);
+        let text = "var s = \"one\n  twö\nthree\";\n";
+        let sources = vec![Source { name: "s.fx".to_string(), text: text.to_string() }];
+        assert_eq!(sources[0].position(8), (1, 9));
+        assert_eq!(sources[0].position(19), (2, 6));
+        assert_eq!(sources[0].position(26), (3, 7));
+        let span = Span { start: 8, end: 26, column: 9, source: 0 };
+        let rendered = Diagnostic::new(Stage::Lex, "Too long.", Some(span)).render(&sources);
+        assert_eq!(rendered, "syntax error: Too long.\n --> s.fx:1:9\n  |\n1 | var s = \"one\n  |         ^^^^\n2 |   twö\n  |   ^^^\n3 | three\";\n  | ^^^^^^\n");
+    }
+}
@@ -9,7 +9,16 @@ use std::cell::RefCell;
 pub struct Environment {
     //Parent Pointer Tree
     pub(crate) enclosing: Option<Rc<RefCell<Environment>>>,
+    //Globals live in the root, keyed by name
     values: HashMap<String, FxUnit>,
+    //Locals in declaration order, the resolver hands out the same slot numbers
+    slots: Vec<FxUnit>,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Environment::new()
+    }
 }
 
 impl Environment {
@@ -17,6 +26,7 @@ impl Environment {
         Environment {
             enclosing: None,
             values: HashMap::new(),
+            slots: Vec::new(),
         }
     }
 
@@ -24,11 +34,36 @@ impl Environment {
         Environment {
             enclosing: Some(Rc::clone(enclosing)),
             values: HashMap::new(),
+            slots: Vec::new(),
         }
     }
 
     pub fn define(&mut self, name: String, value: FxUnit) {
-        self.values.insert(name, value);
+        if self.enclosing.is_some() {
+            self.slots.push(value);
+        } else {
+            self.values.insert(name, value);
+        }
+    }
+
+    //Only this scope, no walking up the chain
+    pub fn lookup(&self, name: &str) -> Option<FxUnit> {
+        self.values.get(name).cloned()
+    }
+
+    //Assigns in this scope only, false when the name is not defined here
+    pub fn set(&mut self, name: &str, value: FxUnit) -> bool {
+        match self.values.get_mut(name) {
+            Some(slot) => {
+                *slot = value;
+                true
+            },
+            None => false
+        }
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.values.keys().cloned().collect()
     }
 
     pub fn get(&self, name: &Token) -> Result<FxUnit, Error> {
@@ -58,30 +93,59 @@ impl Environment {
         }
     }
 
-    fn ancestor(&self, distance: usize) -> Rc<RefCell<Environment>> {
-        let parent = self.enclosing.clone().expect(&format!("No enclosing environment at {}", 1));
-        let mut environment = Rc::clone(&parent);
-        for i in 1..distance {
-            let parent = environment.borrow().enclosing.clone().expect(&format!("No enclosing environment at {}", i));
-            environment = Rc::clone(&parent);
+    //Unresolved names are globals of the file the running code came from, the root of its chain
+    pub fn get_global(&self, name: &Token) -> Result<FxUnit, Error> {
+        match self.enclosing {
+            Some(ref enclosing) => enclosing.borrow().get_global(name),
+            None => self.get(name)
+        }
+    }
+
+    pub fn assign_global(&mut self, name: &Token, val: FxUnit) -> Result<(), Error> {
+        match self.enclosing {
+            Some(ref enclosing) => enclosing.borrow_mut().assign_global(name, val),
+            None => self.assign(name, val)
         }
-        environment
     }
 
-    pub fn get_at(&self, distance: usize, name: &str) -> Result<FxUnit, Error> {
+    fn enclosing(&self) -> &Rc<RefCell<Environment>> {
+        self.enclosing.as_ref().expect("Resolved local is past the outermost scope.")
+    }
+
+    pub fn get_at(&self, distance: usize, slot: usize) -> FxUnit {
         if distance > 0 {
-            Ok(self.ancestor(distance).borrow().values.get(name).expect(&format!("Undefined variable '{}'", name)).clone())
+            self.enclosing().borrow().get_at(distance - 1, slot)
         } else {
-            Ok(self.values.get(name).expect(&format!("Undefined variable '{}'", name)).clone())
+            self.slots.get(slot).unwrap_or_else(|| panic!("No local in slot {}", slot)).clone()
         }
     }
 
-    pub fn assign_at(&mut self, distance: usize, name: &Token, value: FxUnit) -> Result<(), Error> {
+    pub fn assign_at(&mut self, distance: usize, slot: usize, value: FxUnit) {
         if distance > 0 {
-            self.ancestor(distance).borrow_mut().values.insert(name.lexeme.clone(), value);
+            self.enclosing().borrow_mut().assign_at(distance - 1, slot, value)
         } else {
-            self.values.insert(name.lexeme.clone(), value);
+            *self.slots.get_mut(slot).unwrap_or_else(|| panic!("No local in slot {}", slot)) = value;
         }
-        Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locals_by_slot() {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        globals.borrow_mut().define("g".to_string(), FxUnit::Number(0.0));
+        let outer = Rc::new(RefCell::new(Environment::from(&globals)));
+        outer.borrow_mut().define("a".to_string(), FxUnit::Number(1.0));
+        outer.borrow_mut().define("b".to_string(), FxUnit::Number(2.0));
+        let mut inner = Environment::from(&outer);
+        inner.define("c".to_string(), FxUnit::Number(3.0));
+
+        inner.assign_at(1, 1, FxUnit::Number(20.0));
+        assert!(matches!(inner.get_at(1, 1), FxUnit::Number(n) if n == 20.0));
+        assert!(matches!(inner.get_at(0, 0), FxUnit::Number(n) if n == 3.0));
+        assert!(matches!(globals.borrow().lookup("g"), Some(FxUnit::Number(n)) if n == 0.0));
+        assert!(outer.borrow().lookup("a").is_none());
+    }
+}
@@ -1,43 +1,77 @@
-use crate::frontend::tokens::{Token, TokenType};
+use crate::frontend::tokens::Token;
+use crate::frontend::diagnostic::Diagnostic;
 use std::io;
 use std::{convert, fmt};
+use std::time::Duration;
 use crate::frontend::fxunit::FxUnit;
 
-pub fn error(line: i32, message: &str) {
-    report(line, "", message);
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    //Every problem found by the lexer, by the parser, or by the resolver
+    Lex(Vec<Diagnostic>),
+    Parse(Vec<Diagnostic>),
+    Resolve(Vec<Diagnostic>),
+    //Limits of the bytecode format, like too many constants in one function
+    Compile(Vec<Diagnostic>),
+    Runtime { token: Token, message: String },
+    Throw { token: Token, value: Box<FxUnit> },
+    //Raised by natives, which have no token, and re-raised as Runtime at the call site
+    Native { message: String },
+    //A limit set by the host stopped the script, try/catch cannot intercept it
+    Aborted(Abort),
+    //The script called exit(status), also uncatchable, what that ends is up to the host
+    Exit(i32),
 }
 
-pub fn report(line: i32, where_: &str, message: &str) {
-    eprintln!("[line {}] Error{}: {}", line, where_, message);
+#[derive(Debug, Clone, PartialEq)]
+pub enum Abort {
+    StepBudget(u64),
+    Deadline(Duration),
+    Cancelled,
 }
 
-pub fn parser_error(token: &Token, message: &str) {
-    if token.token_type == TokenType::Eof {
-        report(token.line, " at end", message);
-    } else {
-        report(token.line, &format!(" at '{}'", token.lexeme), message);
+impl fmt::Display for Abort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Abort::StepBudget(steps) => write!(f, "Step budget of {} exceeded.", steps),
+            Abort::Deadline(timeout) => write!(f, "Timed out after {:?}.", timeout),
+            Abort::Cancelled => write!(f, "Cancelled by the host."),
+        }
     }
 }
 
-#[derive(Debug)]
-pub enum Error {
-    Io(io::Error),
-    Parse,
-    Runtime { token: Token, message: String },
-    Return { value:FxUnit },
-}
-
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Error::Io(underlying) => write!(f, "IoError {}", underlying),
-            Error::Parse => write!(f, "ParseError"),
+            Error::Lex(diagnostics) => write!(f, "LexError {}", messages(diagnostics)),
+            Error::Parse(diagnostics) => write!(f, "ParseError {}", messages(diagnostics)),
+            Error::Resolve(diagnostics) => write!(f, "ResolveError {}", messages(diagnostics)),
+            Error::Compile(diagnostics) => write!(f, "CompileError {}", messages(diagnostics)),
             Error::Runtime { message, .. } => write!(f, "RuntimeError {}", message),
-            Error::Return { value } => write!(f, "Return {:?}", value),
+            Error::Throw { value, .. } => write!(f, "Uncaught {}", value),
+            Error::Native { message } => write!(f, "RuntimeError {}", message),
+            Error::Aborted(abort) => write!(f, "Aborted {}", abort),
+            Error::Exit(status) => write!(f, "Exit {}", status),
+        }
+    }
+}
+
+impl Error {
+    //Line the error points at, None for errors raised away from any token
+    pub fn line(&self) -> Option<i32> {
+        match self {
+            Error::Runtime { token, .. } | Error::Throw { token, .. } => Some(token.line),
+            _ => None
         }
     }
 }
 
+fn messages(diagnostics: &[Diagnostic]) -> String {
+    diagnostics.iter().map(|d| d.message.as_str()).collect::<Vec<_>>().join(" ")
+}
+
 impl std::error::Error for Error {
     fn description(&self) -> &str {
         "fxlang Error"
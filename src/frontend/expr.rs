@@ -1,42 +1,91 @@
 use crate::frontend::error::Error;
-use crate::frontend::tokens::Token;
+use crate::frontend::tokens::{Span, Token};
+use crate::frontend::stmt::Stmt;
 use std::fmt;
 use std::fmt::Formatter;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 /*
 TODO add docs for Visitor Design Pattern
  */
 pub trait Visitor<T> {
-    fn visit_assign_expr(&mut self, name: &Token, val: &Expr) -> Result<T, Error>;
+    fn visit_assign_expr(&mut self, id: NodeId, name: &Token, val: &Expr) -> Result<T, Error>;
     fn visit_binary_expr(&mut self, lhs: &Expr, rhs: &Expr, op: &Token) -> Result<T, Error>;
-    fn visit_call_expr(&mut self, callee:&Expr, paren:&Token, arguments:&Vec<Expr>)->Result<T,Error>;
+    fn visit_call_expr(&mut self, callee:&Expr, paren:&Token, arguments:&[Expr])->Result<T,Error>;
     fn visit_get_expr(&mut self, object:&Expr,name:&Token)->Result<T,Error>;
     fn visit_set_expr(&mut self, object:&Expr, name:&Token, value:&Expr) -> Result<T,Error>;
-    fn visit_super_expr(&mut self, keyword:&Token, method:&Token) -> Result<T,Error>;
-    fn visit_this_expr(&mut self,keyword:&Token) -> Result<T,Error>;
+    fn visit_super_expr(&mut self, id: NodeId, keyword:&Token, method:&Token) -> Result<T,Error>;
+    fn visit_this_expr(&mut self, id: NodeId, keyword:&Token) -> Result<T,Error>;
     fn visit_grouping_expr(&mut self, expr: &Expr) -> Result<T, Error>;
+    fn visit_lambda_expr(&mut self, keyword: &Token, params: &[Token], body: &[Stmt]) -> Result<T, Error>;
+    fn visit_list_expr(&mut self, bracket: &Token, elements: &[Expr]) -> Result<T, Error>;
+    fn visit_map_expr(&mut self, brace: &Token, entries: &[(Expr, Expr)]) -> Result<T, Error>;
+    fn visit_index_expr(&mut self, object: &Expr, bracket: &Token, index: &Expr) -> Result<T, Error>;
+    fn visit_set_index_expr(&mut self, object: &Expr, bracket: &Token, index: &Expr, value: &Expr) -> Result<T, Error>;
     fn visit_literal_expr(&mut self, val: &LiteralValue) -> Result<T, Error>;
     fn visit_logical_expr(&mut self, lhs: &Expr, rhs: &Expr, op: &Token) -> Result<T, Error>;
     fn visit_unary_expr(&mut self, op: &Token, rhs: &Expr) -> Result<T, Error>;
-    fn visit_variable_expr(&mut self, name: &Token) -> Result<T, Error>;
+    fn visit_variable_expr(&mut self, id: NodeId, name: &Token) -> Result<T, Error>;
+}
+
+/*
+    Identifies one expression that refers to a variable, the resolver's results are keyed by
+    it. Ids are handed out process wide so trees parsed separately, REPL lines and modules,
+    never share one.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+impl NodeId {
+    pub fn fresh() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        NodeId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
 }
 
 impl Expr {
+    //Nodes without a token at each end store their span, the rest are bounded by their parts
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Assign { name, val, .. } => name.span.to(val.span()),
+            Expr::Binary { lhs, rhs, .. } | Expr::Logical { lhs, rhs, .. } => lhs.span().to(rhs.span()),
+            Expr::Call { callee, paren, .. } => callee.span().to(paren.span),
+            Expr::Get { object, name } => object.span().to(name.span),
+            Expr::Set { object, value, .. } => object.span().to(value.span()),
+            Expr::Super { keyword, method, .. } => keyword.span.to(method.span),
+            Expr::This { keyword, .. } => keyword.span,
+            Expr::Index { object, bracket, .. } => object.span().to(bracket.span),
+            Expr::SetIndex { object, value, .. } => object.span().to(value.span()),
+            Expr::Unary { op, rhs } => op.span.to(rhs.span()),
+            Expr::Variable { name, .. } => name.span,
+            Expr::Grouping { span, .. }
+            | Expr::Lambda { span, .. }
+            | Expr::List { span, .. }
+            | Expr::Map { span, .. }
+            | Expr::Literal { span, .. } => *span,
+        }
+    }
+
     //Generics and dyn for Traits
     pub fn accept<T>(&self, v: &mut dyn Visitor<T>) -> Result<T, Error> {
         match self {
-            Expr::Assign { name, val } => v.visit_assign_expr(name, val),
+            Expr::Assign { id, name, val } => v.visit_assign_expr(*id, name, val),
             Expr::Binary { lhs, rhs, op } => v.visit_binary_expr(lhs, rhs, op),
             Expr::Call {callee,paren, arguments} => v.visit_call_expr(callee,paren,arguments),
             Expr::Get {object, name} => v.visit_get_expr(object, name),
             Expr::Set {object,name,value}=>v.visit_set_expr(object,name,value),
-            Expr::Super {keyword, method} => v.visit_super_expr(keyword, method),
-            Expr::This {keyword} => v.visit_this_expr(keyword),
-            Expr::Grouping { expr } => v.visit_grouping_expr(expr),
-            Expr::Literal { val } => v.visit_literal_expr(val),
+            Expr::Super {id, keyword, method} => v.visit_super_expr(*id, keyword, method),
+            Expr::This {id, keyword} => v.visit_this_expr(*id, keyword),
+            Expr::Grouping { expr, .. } => v.visit_grouping_expr(expr),
+            Expr::Lambda { keyword, params, body, .. } => v.visit_lambda_expr(keyword, params, body),
+            Expr::List { bracket, elements, .. } => v.visit_list_expr(bracket, elements),
+            Expr::Map { brace, entries, .. } => v.visit_map_expr(brace, entries),
+            Expr::Index { object, bracket, index } => v.visit_index_expr(object, bracket, index),
+            Expr::SetIndex { object, bracket, index, value } => v.visit_set_index_expr(object, bracket, index, value),
+            Expr::Literal { val, .. } => v.visit_literal_expr(val),
             Expr::Logical { lhs, rhs, op } => v.visit_logical_expr(lhs, rhs, op),
             Expr::Unary { op, rhs } => v.visit_unary_expr(op, rhs),
-            Expr::Variable { name } => v.visit_variable_expr(name),
+            Expr::Variable { id, name } => v.visit_variable_expr(*id, name),
         }
     }
 }
@@ -44,6 +93,7 @@ impl Expr {
 #[derive(Debug, Clone)]
 pub enum Expr {
     Assign {
+        id: NodeId,
         name: Token,
         val: Box<Expr>,
     },
@@ -67,17 +117,49 @@ pub enum Expr {
         value:Box<Expr>
     },
     Super{
+        id:NodeId,
         keyword:Token,
         method:Token
     },
     This{
+        id:NodeId,
         keyword:Token
     },
     Grouping {
         expr: Box<Expr>,
+        span: Span,
+    },
+    //`fn (x) -> x * 2` is stored with its body already wrapped in a return
+    Lambda {
+        keyword: Token,
+        params: Vec<Token>,
+        body: Vec<Stmt>,
+        span: Span,
+    },
+    List {
+        bracket: Token,
+        elements: Vec<Expr>,
+        span: Span,
+    },
+    Map {
+        brace: Token,
+        entries: Vec<(Expr, Expr)>,
+        span: Span,
+    },
+    Index {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+    },
+    SetIndex {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+        value: Box<Expr>,
     },
     Literal {
         val: LiteralValue,
+        span: Span,
     },
     Logical {
         lhs: Box<Expr>,
@@ -89,6 +171,7 @@ pub enum Expr {
         rhs: Box<Expr>,
     },
     Variable {
+        id: NodeId,
         name: Token,
     },
 }
@@ -103,7 +186,8 @@ pub enum LiteralValue {
 
 impl fmt::Display for Expr {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "{}", self)
+        let printed = AstPrinter.print(self.clone()).map_err(|_| fmt::Error)?;
+        write!(f, "{}", printed)
     }
 }
 
@@ -140,7 +224,7 @@ impl AstPrinter {
 }
 
 impl Visitor<String> for AstPrinter {
-    fn visit_assign_expr(&mut self, name: &Token, val: &Expr) -> Result<String, Error> {
+    fn visit_assign_expr(&mut self, _id: NodeId, name: &Token, val: &Expr) -> Result<String, Error> {
         self.parenthesize(name.lexeme.clone(), vec![val])
     }
 
@@ -148,30 +232,58 @@ impl Visitor<String> for AstPrinter {
         self.parenthesize(op.lexeme.clone(), vec![lhs, rhs])
     }
 
-    fn visit_call_expr(&mut self, callee: &Expr, paren: &Token, arguments: &Vec<Expr>) -> Result<String, Error> {
-        todo!()
+    fn visit_call_expr(&mut self, callee: &Expr, _paren: &Token, arguments: &[Expr]) -> Result<String, Error> {
+        let mut exprs = vec![callee];
+        exprs.extend(arguments.iter());
+        self.parenthesize("call".to_string(), exprs)
     }
 
     fn visit_get_expr(&mut self, object: &Expr, name: &Token) -> Result<String, Error> {
-        todo!()
+        self.parenthesize(format!(".{}", name.lexeme), vec![object])
     }
 
     fn visit_set_expr(&mut self, object: &Expr, name: &Token, value: &Expr) -> Result<String, Error> {
-        todo!()
+        self.parenthesize(format!("set .{}", name.lexeme), vec![object, value])
     }
 
-    fn visit_super_expr(&mut self, keyword: &Token, method: &Token) -> Result<String, Error> {
-        todo!()
+    fn visit_super_expr(&mut self, _id: NodeId, _keyword: &Token, method: &Token) -> Result<String, Error> {
+        Ok(format!("super.{}", method.lexeme))
     }
 
-    fn visit_this_expr(&mut self, keyword: &Token) -> Result<String, Error> {
-        todo!()
+    fn visit_this_expr(&mut self, _id: NodeId, _keyword: &Token) -> Result<String, Error> {
+        Ok("this".to_string())
     }
 
     fn visit_grouping_expr(&mut self, expr: &Expr) -> Result<String, Error> {
         self.parenthesize("group".to_string(), vec![expr])
     }
 
+    fn visit_lambda_expr(&mut self, _keyword: &Token, params: &[Token], _body: &[Stmt]) -> Result<String, Error> {
+        let names: Vec<&str> = params.iter().map(|p| p.lexeme.as_str()).collect();
+        Ok(format!("(fn ({}))", names.join(" ")))
+    }
+
+    fn visit_list_expr(&mut self, _bracket: &Token, elements: &[Expr]) -> Result<String, Error> {
+        self.parenthesize("list".to_string(), elements.iter().collect())
+    }
+
+    fn visit_map_expr(&mut self, _brace: &Token, entries: &[(Expr, Expr)]) -> Result<String, Error> {
+        let mut exprs = Vec::new();
+        for (key, value) in entries {
+            exprs.push(key);
+            exprs.push(value);
+        }
+        self.parenthesize("map".to_string(), exprs)
+    }
+
+    fn visit_index_expr(&mut self, object: &Expr, _bracket: &Token, index: &Expr) -> Result<String, Error> {
+        self.parenthesize("index".to_string(), vec![object, index])
+    }
+
+    fn visit_set_index_expr(&mut self, object: &Expr, _bracket: &Token, index: &Expr, value: &Expr) -> Result<String, Error> {
+        self.parenthesize("set-index".to_string(), vec![object, index, value])
+    }
+
     fn visit_literal_expr(&mut self, val: &LiteralValue) -> Result<String, Error> {
         Ok(val.to_string())
     }
@@ -184,7 +296,7 @@ impl Visitor<String> for AstPrinter {
         self.parenthesize(op.lexeme.clone(), vec![rhs])
     }
 
-    fn visit_variable_expr(&mut self, name: &Token) -> Result<String, Error> {
+    fn visit_variable_expr(&mut self, _id: NodeId, name: &Token) -> Result<String, Error> {
         Ok(name.lexeme.clone())
     }
 }
@@ -193,7 +305,7 @@ impl Visitor<String> for AstPrinter {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::frontend::tokens::{TokenType, Token};
+    use crate::frontend::tokens::{Span, TokenType, Token};
 
     #[test]
     fn default_test() {
@@ -203,6 +315,7 @@ mod tests {
                 op: Token::new(TokenType::Minus, "-", 1),
                 rhs: Box::new(Expr::Literal {
                     val: LiteralValue::Number(420f64),
+                    span: Span::default(),
                 }),
             }),
             op: Token::new(TokenType::Star, "*", 1),
@@ -210,9 +323,11 @@ mod tests {
                 Expr::Grouping {
                     expr: Box::new(
                         Expr::Literal {
-                            val: LiteralValue::Number(421f64)
+                            val: LiteralValue::Number(421f64),
+                            span: Span::default(),
                         }
-                    )
+                    ),
+                    span: Span::default(),
                 }
             ),
         };
@@ -222,4 +337,20 @@ mod tests {
             "(* (- 420) (group 421))"
         )
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn spans_cover_source() {
+        let src = "var xs = [1, (2 + 3)];\nprint xs[1] * 2;";
+        let tokens = crate::frontend::lexer::Lexer::new(src.to_string()).scan_tokens().unwrap();
+        let statements = crate::frontend::parser::Parser::new(tokens).parse().unwrap();
+        let text = |span: Span| &src[span.start..span.end];
+        assert_eq!(text(statements[0].span()), "var xs = [1, (2 + 3)];");
+        assert_eq!(text(statements[1].span()), "print xs[1] * 2;");
+        if let Stmt::Print { expr, .. } = &statements[1] {
+            assert_eq!(text(expr.span()), "xs[1] * 2");
+            assert_eq!(expr.span().column, 7);
+        } else {
+            panic!("expected a print statement");
+        }
+    }
+}
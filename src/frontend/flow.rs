@@ -0,0 +1,13 @@
+use crate::frontend::fxunit::FxUnit;
+
+/*
+    How a statement finished. Return, break and continue unwind through blocks as a Flow,
+    so Error only ever carries real failures and those always propagate.
+*/
+#[derive(Debug, Clone)]
+pub enum Flow {
+    Normal,
+    Return(FxUnit),
+    Break,
+    Continue,
+}
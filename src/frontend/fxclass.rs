@@ -4,7 +4,10 @@ use crate::frontend::fxunit::FxUnit;
 use std::collections::HashMap;
 use crate::frontend::tokens::Token;
 use crate::frontend::error::Error;
-use crate::frontend::fxfx::FxFx;
+use crate::frontend::fxfx::{Arity, FxFx};
+use crate::frontend::interpreter::Interpreter;
+use std::any::Any;
+use std::marker::PhantomData;
 
 #[derive(Debug)]
 pub struct FxClass{
@@ -16,7 +19,7 @@ pub struct FxClass{
 impl FxClass{
     pub fn find_method(&self, name:&str)->Option<FxFx>{
         if self.methods.contains_key(name){
-            self.methods.get(name).map(|f| f.clone())
+            self.methods.get(name).cloned()
         }else{
             if let Some(ref superclass) = self.superclass{
                 superclass.borrow().find_method(name)
@@ -30,14 +33,22 @@ impl FxClass{
 #[derive(Debug)]
 pub struct FxClassInstance{
     pub class:Rc<RefCell<FxClass>>,
-    fields:HashMap<String, FxUnit>
+    fields:HashMap<String, FxUnit>,
+    //Rust value behind an instance of a native class, set by its init
+    native:Option<Rc<RefCell<dyn Any>>>
 }
 
 impl FxClassInstance {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(class:&Rc<RefCell<FxClass>>) -> FxUnit {
+        FxClassInstance::with_fields(class, HashMap::new())
+    }
+
+    pub fn with_fields(class:&Rc<RefCell<FxClass>>, fields:HashMap<String, FxUnit>) -> FxUnit {
         let instance = FxClassInstance{
             class:Rc::clone(class),
-            fields:HashMap::new()
+            fields,
+            native:None
         };
         FxUnit::Instance(Rc::new(RefCell::new(instance)))
     }
@@ -60,4 +71,70 @@ impl FxClassInstance {
     pub fn set(&mut self, name:&Token, value:FxUnit){
         self.fields.insert(name.lexeme.clone(),value);
     }
-}
\ No newline at end of file
+
+    pub fn native(&self) -> Option<Rc<RefCell<dyn Any>>> {
+        self.native.clone()
+    }
+}
+
+/*
+    Builds a class whose instances wrap a Rust value of type T. `init` runs the Rust
+    constructor and methods get the value mutably, fx classes can extend it with `<`.
+*/
+pub struct NativeClass<T> {
+    name:String,
+    methods:HashMap<String,FxFx>,
+    value:PhantomData<T>
+}
+
+impl<T: Any> NativeClass<T> {
+    pub fn new(name:&str) -> Self {
+        NativeClass {
+            name:name.to_string(),
+            methods:HashMap::new(),
+            value:PhantomData
+        }
+    }
+
+    pub fn init<F>(mut self, arity:Arity, constructor:F) -> Self
+        where F: Fn(&mut Interpreter, &[FxUnit]) -> Result<T, Error> + 'static {
+        let init = FxFx::native("init", arity, move |interpreter, args| {
+            let value = constructor(interpreter, &args[1..])?;
+            if let FxUnit::Instance(ref instance) = args[0] {
+                instance.borrow_mut().native = Some(Rc::new(RefCell::new(value)));
+            }
+            Ok(args[0].clone())
+        });
+        self.methods.insert("init".to_string(), init);
+        self
+    }
+
+    pub fn method<F>(mut self, name:&str, arity:Arity, body:F) -> Self
+        where F: Fn(&mut Interpreter, &mut T, &[FxUnit]) -> Result<FxUnit, Error> + 'static {
+        let class_name = self.name.clone();
+        let method_name = name.to_string();
+        let method = FxFx::native(name, arity, move |interpreter, args| {
+            let cell = match args[0] {
+                FxUnit::Instance(ref instance) => instance.borrow().native(),
+                _ => None
+            };
+            let missing = || Error::Native { message: format!("{}.{} called on an instance without a {} value.", class_name, method_name, class_name) };
+            let cell = cell.ok_or_else(missing)?;
+            let mut value = cell.try_borrow_mut().map_err(|_| Error::Native {
+                message: format!("{}.{} called while the {} value is in use.", class_name, method_name, class_name)
+            })?;
+            let value = value.downcast_mut::<T>().ok_or_else(missing)?;
+            body(interpreter, value, &args[1..])
+        });
+        self.methods.insert(name.to_string(), method);
+        self
+    }
+
+    pub fn build(self) -> Rc<RefCell<FxClass>> {
+        Rc::new(RefCell::new(FxClass {
+            name:self.name,
+            superclass:None,
+            methods:self.methods
+        }))
+    }
+}
@@ -1,18 +1,55 @@
 use crate::frontend::fxunit::FxUnit;
-use crate::frontend::tokens::{Token, TokenType};
+use crate::frontend::tokens::Token;
 use crate::frontend::stmt::Stmt;
 use crate::frontend::env::Environment;
 use std::rc::Rc;
 use std::cell::RefCell;
 use crate::frontend::interpreter::Interpreter;
 use crate::frontend::error::Error;
+use crate::frontend::flow::Flow;
+use crate::frontend::convert::TypedNative;
+use crate::vm::machine::{self, Closure};
 use std::fmt;
 
+/*
+    Rust side of a native callable. It may capture state, call back into fx through the
+    interpreter and fail with Error::Native, which the call site turns into a runtime error.
+*/
+pub type NativeBody = dyn Fn(&mut Interpreter, &[FxUnit]) -> Result<FxUnit, Error>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+    Exact(usize),
+    Range(usize, usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, count: usize) -> bool {
+        match *self {
+            Arity::Exact(n) => count == n,
+            Arity::Range(min, max) => count >= min && count <= max,
+            Arity::AtLeast(min) => count >= min,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Arity::Exact(n) => write!(f, "{}", n),
+            Arity::Range(min, max) => write!(f, "{} to {}", min, max),
+            Arity::AtLeast(min) => write!(f, "at least {}", min),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub enum FxFx{
     Native{
-        arity:usize,
-        body: Box<fn(&Vec<FxUnit>)->FxUnit>
+        name:String,
+        arity:Arity,
+        body: Rc<NativeBody>
     },
 
     User{
@@ -21,52 +58,84 @@ pub enum FxFx{
         body:Vec<Stmt>,
         closure:Rc<RefCell<Environment>>,
         is_init:bool
+    },
+
+    //Compiled for the bytecode Vm, methods carry their instance once bound
+    Compiled{
+        closure:Rc<Closure>,
+        this:Option<Box<FxUnit>>
     }
 }
 
 impl FxFx{
-    pub fn call(&self, interpreter:&mut Interpreter, args:&Vec<FxUnit>)->Result<FxUnit,Error>{
+    pub fn native<F>(name:&str, arity:Arity, body:F) -> Self
+        where F: Fn(&mut Interpreter, &[FxUnit]) -> Result<FxUnit, Error> + 'static {
+        FxFx::Native {
+            name:name.to_string(),
+            arity,
+            body:Rc::new(body)
+        }
+    }
+
+    //A native from a Rust fn whose parameters and return convert through FromFx/IntoFx
+    pub fn typed<F, Args>(name:&str, f:F) -> Self
+        where F: TypedNative<Args> + 'static {
+        let arity = Arity::Exact(f.arity());
+        FxFx::native(name, arity, move |_, args| f.invoke(args))
+    }
+
+    pub fn call(&self, interpreter:&mut Interpreter, args:&[FxUnit])->Result<FxUnit,Error>{
         match self {
             FxFx::Native {body,..}=>{
-                Ok(body(args))
+                body(interpreter, args)
             },
             FxFx::User {params,body,closure,is_init,..}=>{
-                let mut env = Rc::new(RefCell::new(Environment::from(closure)));
+                let env = Rc::new(RefCell::new(Environment::from(closure)));
                 for (param,arg) in params.iter().zip(args.iter()){
                     env.borrow_mut().define(param.lexeme.clone(), arg.clone());
                 }
-                match interpreter.exec_block(body,env) {
-                    Err(Error::Return{value}) => {
-                        if *is_init {
-                            Ok(closure.borrow().get_at(0,"this").expect("Initializer should return 'this'"))
-                        }else{
-                            Ok(value)
-                        }
-                    },
-                    Err(other)=>Err(other),
-                    Ok(..)=> {
-                        if *is_init {
-                            Ok(closure.borrow().get_at(0,"this").expect("Initializer should return 'this'"))
-                        }else{
-                            Ok(FxUnit::Nil)
-                        }
-                    }
+                let flow = interpreter.exec_block(body,env)?;
+                if *is_init {
+                    Ok(closure.borrow().get_at(0, 0))
+                }else if let Flow::Return(value) = flow {
+                    Ok(value)
+                }else{
+                    Ok(FxUnit::Nil)
                 }
-            }
+            },
+            FxFx::Compiled {closure,this}=>machine::call(interpreter, closure, this.as_deref(), args)
         }
     }
-    pub fn arity(&self)->usize{
+    pub fn name(&self)->String{
+        match self {
+            FxFx::Native {name,..}=>name.clone(),
+            FxFx::User {name,..}=>name.lexeme.clone(),
+            FxFx::Compiled {closure,..}=>closure.function.name.clone()
+        }
+    }
+
+    pub fn arity(&self)->Arity{
         match self {
             FxFx::Native {arity,..}=>*arity,
-            FxFx::User {params, ..}=> params.len()
+            FxFx::User {params, ..}=> Arity::Exact(params.len()),
+            FxFx::Compiled {closure, ..}=> Arity::Exact(closure.function.arity)
         }
     }
 
     pub fn bind(&self, instance:FxUnit) -> Self {
         match self {
-            FxFx::Native {body, ..} => unreachable!(),
+            //Native methods get the instance as their first argument
+            FxFx::Native {name,arity,body} => {
+                let body = Rc::clone(body);
+                FxFx::native(name, *arity, move |interpreter, args| {
+                    let mut with_this = Vec::with_capacity(args.len() + 1);
+                    with_this.push(instance.clone());
+                    with_this.extend_from_slice(args);
+                    body(interpreter, &with_this)
+                })
+            },
             FxFx::User {name,params,body,closure, is_init} => {
-                let mut env = Rc::new(RefCell::new(Environment::from(closure)));
+                let env = Rc::new(RefCell::new(Environment::from(closure)));
                 env.borrow_mut().define("this".to_string(),instance);
                 FxFx::User {
                     name: name.clone(),
@@ -75,6 +144,10 @@ impl FxFx{
                     closure: env,
                     is_init:*is_init
                 }
+            },
+            FxFx::Compiled {closure, ..} => FxFx::Compiled {
+                closure: Rc::clone(closure),
+                this: Some(Box::new(instance))
             }
         }
     }
@@ -83,8 +156,9 @@ impl FxFx{
 impl fmt::Debug for FxFx {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            FxFx::Native { .. } => write!(f, "<native func>"),
+            FxFx::Native { name, .. } => write!(f, "<native fn {}>", name),
             FxFx::User { name, .. } => write!(f, "<fn {}>", name.lexeme),
+            FxFx::Compiled { closure, .. } => write!(f, "<fn {}>", closure.function.name),
         }
     }
 }
@@ -92,8 +166,9 @@ impl fmt::Debug for FxFx {
 impl fmt::Display for FxFx {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            FxFx::Native { .. } => write!(f, "<native func>"),
+            FxFx::Native { name, .. } => write!(f, "<native fn {}>", name),
             FxFx::User { name, .. } => write!(f, "<fn {}>", name.lexeme),
+            FxFx::Compiled { closure, .. } => write!(f, "<fn {}>", closure.function.name),
         }
     }
 }
\ No newline at end of file
@@ -1,7 +1,11 @@
 use crate::frontend::fxfx::FxFx;
 use std::cell::RefCell;
 use std::rc::Rc;
+use std::fmt;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use crate::frontend::fxclass::{FxClassInstance, FxClass};
+use crate::frontend::module::FxModule;
 
 #[derive(Debug, Clone)]
 pub enum FxUnit {
@@ -10,11 +14,39 @@ pub enum FxUnit {
     Nil,
     Number(f64),
     String(String),
+    //Lists and maps are shared, so every alias sees a push or an index assignment
+    List(Rc<RefCell<Vec<FxUnit>>>),
+    Map(Rc<RefCell<BTreeMap<MapKey, FxUnit>>>),
     Instance(Rc<RefCell<FxClassInstance>>),
-    Class(Rc<RefCell<FxClass>>)
+    Class(Rc<RefCell<FxClass>>),
+    Module(Rc<FxModule>)
 }
 
 impl FxUnit {
+    pub fn list(elements: Vec<FxUnit>) -> FxUnit {
+        FxUnit::List(Rc::new(RefCell::new(elements)))
+    }
+
+    pub fn map(entries: BTreeMap<MapKey, FxUnit>) -> FxUnit {
+        FxUnit::Map(Rc::new(RefCell::new(entries)))
+    }
+
+    //How conversion errors and messages name the value's type
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            FxUnit::Boolean(_) => "bool",
+            FxUnit::Callable(_) => "function",
+            FxUnit::Nil => "nil",
+            FxUnit::Number(_) => "number",
+            FxUnit::String(_) => "string",
+            FxUnit::List(_) => "list",
+            FxUnit::Map(_) => "map",
+            FxUnit::Instance(_) => "instance",
+            FxUnit::Class(_) => "class",
+            FxUnit::Module(_) => "module",
+        }
+    }
+
     pub fn equals(&self, other: &FxUnit) -> bool {
         match (self, other) {
             (FxUnit::Nil, FxUnit::Nil) => true,
@@ -23,7 +55,183 @@ impl FxUnit {
             (FxUnit::Boolean(left), FxUnit::Boolean(right)) => left == right,
             (FxUnit::Number(left), FxUnit::Number(right)) => left == right,
             (FxUnit::String(left), FxUnit::String(right)) => left == right,
+            (FxUnit::List(left), FxUnit::List(right)) => Rc::ptr_eq(left, right),
+            (FxUnit::Map(left), FxUnit::Map(right)) => Rc::ptr_eq(left, right),
             _ => false //TODO define for class and instance
         }
     }
-}
\ No newline at end of file
+}
+
+impl fmt::Display for FxUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_to(f, &mut Vec::new())
+    }
+}
+
+impl FxUnit {
+    /*
+        `printing` holds the lists and maps currently being written, one that contains
+        itself shows up again as [...] or {...} instead of recursing until the stack runs out.
+    */
+    fn write_to(&self, f: &mut fmt::Formatter<'_>, printing: &mut Vec<*const ()>) -> fmt::Result {
+        match self {
+            FxUnit::Boolean(b) => write!(f, "{}", b),
+            FxUnit::Callable(func) => write!(f, "{}", func),
+            FxUnit::Nil => write!(f, "NIL"),
+            FxUnit::Number(n) => write!(f, "{}", n),
+            FxUnit::String(s) => write!(f, "{}", s),
+            FxUnit::List(elements) => {
+                let id = Rc::as_ptr(elements) as *const ();
+                if printing.contains(&id) {
+                    return write!(f, "[...]");
+                }
+                printing.push(id);
+                write!(f, "[")?;
+                for (i, element) in elements.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    element.write_nested(f, printing)?;
+                }
+                printing.pop();
+                write!(f, "]")
+            },
+            FxUnit::Map(entries) => {
+                let id = Rc::as_ptr(entries) as *const ();
+                if printing.contains(&id) {
+                    return write!(f, "{{...}}");
+                }
+                printing.push(id);
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: ", key)?;
+                    value.write_nested(f, printing)?;
+                }
+                printing.pop();
+                write!(f, "}}")
+            },
+            FxUnit::Class(c) => write!(f, "{}", c.borrow().name),
+            FxUnit::Instance(i) => write!(f, "{} instance", i.borrow().class.borrow().name),
+            FxUnit::Module(m) => write!(f, "<module {}>", m.name),
+        }
+    }
+
+    //Quote nested strings so ["a, b"] reads differently from ["a", "b"]
+    fn write_nested(&self, f: &mut fmt::Formatter<'_>, printing: &mut Vec<*const ()>) -> fmt::Result {
+        match self {
+            FxUnit::String(s) => write!(f, "{:?}", s),
+            other => other.write_to(f, printing),
+        }
+    }
+}
+
+/*
+    Keys a map can hold. The map is ordered by key (booleans, then numbers, then strings)
+    so printing a map or walking its keys gives the same result on every run.
+*/
+#[derive(Debug, Clone)]
+pub enum MapKey {
+    Boolean(bool),
+    Number(f64),
+    String(String),
+}
+
+impl MapKey {
+    //None for values that cannot be keys, NaN included since it never equals itself
+    pub fn from_unit(unit: &FxUnit) -> Option<MapKey> {
+        match unit {
+            FxUnit::Boolean(b) => Some(MapKey::Boolean(*b)),
+            FxUnit::Number(n) if n.is_nan() => None,
+            //-0 and 0 compare equal so they must land on the same entry
+            FxUnit::Number(n) => Some(MapKey::Number(if *n == 0.0 { 0.0 } else { *n })),
+            FxUnit::String(s) => Some(MapKey::String(s.clone())),
+            _ => None
+        }
+    }
+
+    pub fn to_unit(&self) -> FxUnit {
+        match self {
+            MapKey::Boolean(b) => FxUnit::Boolean(*b),
+            MapKey::Number(n) => FxUnit::Number(*n),
+            MapKey::String(s) => FxUnit::String(s.clone()),
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            MapKey::Boolean(_) => 0,
+            MapKey::Number(_) => 1,
+            MapKey::String(_) => 2,
+        }
+    }
+}
+
+impl PartialEq for MapKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for MapKey {}
+
+impl PartialOrd for MapKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for MapKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (MapKey::Boolean(left), MapKey::Boolean(right)) => left.cmp(right),
+            (MapKey::Number(left), MapKey::Number(right)) => left.total_cmp(right),
+            (MapKey::String(left), MapKey::String(right)) => left.cmp(right),
+            _ => self.rank().cmp(&other.rank())
+        }
+    }
+}
+
+impl fmt::Display for MapKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapKey::Boolean(b) => write!(f, "{}", b),
+            MapKey::Number(n) => write!(f, "{}", n),
+            MapKey::String(s) => write!(f, "{:?}", s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cycles_print_once() {
+        let xs = FxUnit::list(vec![FxUnit::Number(1.0)]);
+        if let FxUnit::List(ref elements) = xs {
+            elements.borrow_mut().push(xs.clone());
+        }
+        assert_eq!(xs.to_string(), "[1, [...]]");
+
+        let mut entries = BTreeMap::new();
+        entries.insert(MapKey::String("xs".to_string()), xs.clone());
+        let map = FxUnit::map(entries);
+        if let FxUnit::Map(ref entries) = map {
+            entries.borrow_mut().insert(MapKey::String("me".to_string()), map.clone());
+        }
+        assert_eq!(map.to_string(), "{\"me\": {...}, \"xs\": [1, [...]]}");
+
+        //Sharing without a cycle still prints every copy
+        let shared = FxUnit::list(vec![FxUnit::String("a".to_string())]);
+        assert_eq!(FxUnit::list(vec![shared.clone(), shared]).to_string(), "[[\"a\"], [\"a\"]]");
+
+        //Break the cycles so the test does not leak them
+        if let (FxUnit::List(elements), FxUnit::Map(entries)) = (&xs, &map) {
+            elements.borrow_mut().clear();
+            entries.borrow_mut().clear();
+        }
+    }
+}
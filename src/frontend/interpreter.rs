@@ -1,96 +1,440 @@
-use crate::frontend::error::Error;
+use crate::frontend::error::{Abort, Error};
 use crate::frontend::tokens::{TokenType, Token};
-use crate::frontend::fxunit::FxUnit;
+use crate::frontend::fxunit::{FxUnit, MapKey};
 use crate::frontend::expr::*;
 use crate::frontend::{expr, stmt};
 use crate::frontend::stmt::Stmt;
 use std::rc::Rc;
 use std::cell::RefCell;
 use crate::frontend::env::Environment;
-use std::time::{SystemTime, UNIX_EPOCH};
-use crate::frontend::fxfx::FxFx;
-use std::collections::HashMap;
-use std::io;
+use crate::frontend::fxfx::{Arity, FxFx};
+use std::collections::{BTreeMap, HashMap};
 use crate::frontend::fxclass::{FxClass, FxClassInstance};
+use crate::frontend::natives;
+use crate::frontend::capability::{Capabilities, Capability};
+use crate::vm::machine::Stack;
+use crate::frontend::flow::Flow;
+use crate::frontend::module::FxModule;
+use crate::frontend::diagnostic::Source;
+use crate::frontend::trace::{Frame, Traceback, HOST_LINE};
+use crate::frontend::lexer::Lexer;
+use crate::frontend::parser::Parser;
+use crate::frontend::resolver::Resolver;
+use std::path::{Path, PathBuf};
+use std::{fs, mem};
+use std::io::{self, BufRead, BufReader, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+//Half of a spawned thread's 2MB default, the rest is for the host and the frames between checks
+pub const DEFAULT_MAX_STACK: usize = 1024 * 1024;
+const DEADLINE_CHECK_INTERVAL: u64 = 256;
 
 pub struct Interpreter {
     pub globals:Rc<RefCell<Environment>>,
     env: Rc<RefCell<Environment>>,
-    locals:HashMap<Token, usize>  //TODO Fix this locals fucks up the for loop init
+    //Where each resolved variable reference finds its local, as (depth, slot)
+    locals:HashMap<NodeId, (usize, usize)>,
+    //Class of the values a catch block receives for runtime errors
+    error_class:Rc<RefCell<FxClass>>,
+    //Loaded modules by canonical path, each file runs once
+    modules:HashMap<PathBuf, Rc<FxModule>>,
+    //Files currently executing, the importing file is last
+    import_stack:Vec<PathBuf>,
+    //Names exported so far by the module being executed
+    exports:Vec<String>,
+    //Where print writes and readNum/readString read, stdio unless embedded
+    output:Box<dyn Write>,
+    input:Box<dyn BufRead>,
+    //Every source loaded, so spans in errors can be shown against their text
+    sources:Vec<Source>,
+    //Calls in progress, and the stack as it stood when the current error was raised
+    call_stack:Vec<Frame>,
+    traceback:Option<Traceback>,
+    //Calls deeper than this fail with a runtime error instead of overflowing the Rust stack
+    max_call_depth:usize,
+    //Bytes of Rust stack a run may use below stack_base, what a call costs varies with the code
+    max_stack:usize,
+    stack_base:Option<usize>,
+    //Host limits on a run, checked before every statement. See start_run
+    steps:u64,
+    step_budget:Option<u64>,
+    timeout:Option<Duration>,
+    deadline:Option<Instant>,
+    cancelled:Arc<AtomicBool>,
+    //Native groups installed in globals, modules get the same ones
+    capabilities:Capabilities,
+    //Values and upvalues of bytecode running on this interpreter
+    pub(crate) stack:Stack
+}
+
+/*
+    Sets up an interpreter for untrusted scripts: only the native groups allowed here are
+    installed, the others fail with a runtime error naming the missing capability.
+
+        let interpreter = Interpreter::builder()
+            .allow(Capability::Time)
+            .output(Box::new(buffer))
+            .build();
+*/
+pub struct InterpreterBuilder {
+    capabilities:Capabilities,
+    output:Option<Box<dyn Write>>,
+    input:Option<Box<dyn BufRead>>
+}
+
+impl InterpreterBuilder {
+    pub fn allow(mut self, capability: Capability) -> Self {
+        self.capabilities = self.capabilities.with(capability);
+        self
+    }
+
+    pub fn deny(mut self, capability: Capability) -> Self {
+        self.capabilities = self.capabilities.without(capability);
+        self
+    }
+
+    pub fn capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    //print and readNum/readString use stdio unless these are given
+    pub fn output(mut self, output: Box<dyn Write>) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    pub fn input(mut self, input: Box<dyn BufRead>) -> Self {
+        self.input = Some(input);
+        self
+    }
+
+    pub fn build(self) -> Interpreter {
+        let output = self.output.unwrap_or_else(|| Box::new(io::stdout()));
+        let input = self.input.unwrap_or_else(|| Box::new(BufReader::new(io::stdin())));
+        Interpreter::build(output, input, self.capabilities)
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Self {
-        let globals = Rc::new(RefCell::new(Environment::new()));
+        Interpreter::with_io(Box::new(io::stdout()), Box::new(BufReader::new(io::stdin())))
+    }
 
-        //Native Clock Func
-        let clock:FxUnit = FxUnit::Callable(
-            FxFx::Native{
-                arity:0,
-                body: Box::new(|args:&Vec<FxUnit>|{
-                    FxUnit::Number(
-                        SystemTime::now().duration_since(UNIX_EPOCH).expect("Could not get time.").as_secs_f64() as f64
-                    )
-                })
-            }
-        );
-        globals.borrow_mut().define("clock".to_string(), clock);
-
-        //Native IO
-        pub fn get_input() -> String {
-            let mut buffer = String::new();
-            std::io::stdin().read_line(&mut buffer).expect("Failed to get input fxfx hung.");
-            buffer
-        }
-
-        let readNum:FxUnit = FxUnit::Callable(
-            FxFx::Native {
-                arity:0,
-                body: Box::new(|args:&Vec<FxUnit>|{
-                    FxUnit::Number(
-                        get_input().trim().parse().unwrap()
-                    )
-                })
-            }
-        );
-        globals.borrow_mut().define("readNum".to_string(),readNum);
-        //TODO can we refactor this IO mod??
-        let readString:FxUnit = FxUnit::Callable(
-            FxFx::Native {
-                arity:0,
-                body:Box::new(|args:&Vec<FxUnit>|{
-                    FxUnit::String(
-                        get_input().trim().to_string()
-                    )
-                })
-            }
-        );
-        globals.borrow_mut().define("readString".to_string(),readString);
+    pub fn with_io(output: Box<dyn Write>, input: Box<dyn BufRead>) -> Self {
+        Interpreter::build(output, input, Capabilities::default())
+    }
+
+    //Starts from no capabilities, see InterpreterBuilder
+    pub fn builder() -> InterpreterBuilder {
+        InterpreterBuilder {
+            capabilities:Capabilities::none(),
+            output:None,
+            input:None
+        }
+    }
 
+    fn build(output: Box<dyn Write>, input: Box<dyn BufRead>, capabilities: Capabilities) -> Self {
+        let globals = Interpreter::new_globals(capabilities);
         Interpreter {
             globals:Rc::clone(&globals),
             env: Rc::clone(&globals),
-            locals:HashMap::new()
+            locals:HashMap::new(),
+            error_class:Rc::new(RefCell::new(FxClass{
+                name:"RuntimeError".to_string(),
+                superclass:None,
+                methods:HashMap::new()
+            })),
+            modules:HashMap::new(),
+            import_stack:Vec::new(),
+            exports:Vec::new(),
+            output,
+            input,
+            sources:Vec::new(),
+            call_stack:Vec::new(),
+            traceback:None,
+            max_call_depth:DEFAULT_MAX_CALL_DEPTH,
+            max_stack:DEFAULT_MAX_STACK,
+            stack_base:None,
+            steps:0,
+            step_budget:None,
+            timeout:None,
+            deadline:None,
+            cancelled:Arc::new(AtomicBool::new(false)),
+            capabilities,
+            stack:Stack::default()
+        }
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    pub fn output(&mut self) -> &mut dyn Write {
+        &mut self.output
+    }
+
+    pub fn input(&mut self) -> &mut dyn BufRead {
+        &mut self.input
+    }
+
+    //Every script and every module starts from its own copy of the natives
+    fn new_globals(capabilities: Capabilities) -> Rc<RefCell<Environment>> {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        natives::define_natives(&mut globals.borrow_mut(), capabilities);
+        globals
+    }
+
+    //Imports in the script are resolved relative to this file
+    pub fn set_script_path(&mut self, path: &Path) {
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.import_stack = vec![path];
+    }
+
+    //Loads a file once and runs it with `run`, so each backend runs the modules it imports
+    pub(crate) fn import_module(&mut self, keyword: &Token, path: &str, run: fn(&mut Interpreter, &[Stmt]) -> Result<(), Error>) -> Result<Rc<FxModule>, Error> {
+        let base = match self.import_stack.last().and_then(|file| file.parent()) {
+            Some(dir) => dir.to_path_buf(),
+            None => PathBuf::from(".")
+        };
+        let import_error = |message: String| Error::Runtime { token: keyword.clone(), message };
+        let full_path = fs::canonicalize(base.join(path))
+            .map_err(|e| import_error(format!("Cannot import '{}': {}", path, e)))?;
+
+        if let Some(module) = self.modules.get(&full_path) {
+            return Ok(Rc::clone(module));
+        }
+        if self.import_stack.contains(&full_path) {
+            let chain: Vec<String> = self.import_stack.iter()
+                .chain(std::iter::once(&full_path))
+                .map(|p| p.display().to_string())
+                .collect();
+            return Err(import_error(format!("Circular import: {}", chain.join(" -> "))));
+        }
+
+        let src = fs::read_to_string(&full_path)
+            .map_err(|e| import_error(format!("Cannot import '{}': {}", path, e)))?;
+        let statements = self.load(&full_path.display().to_string(), src)?;
+
+        //Run the module against its own globals, then put the importer's state back
+        let module_globals = Interpreter::new_globals(self.capabilities);
+        let prev_globals = mem::replace(&mut self.globals, Rc::clone(&module_globals));
+        let prev_env = mem::replace(&mut self.env, Rc::clone(&module_globals));
+        let prev_exports = mem::take(&mut self.exports);
+        self.import_stack.push(full_path.clone());
+        let result = run(self, &statements);
+        self.import_stack.pop();
+        let exports = mem::replace(&mut self.exports, prev_exports);
+        self.env = prev_env;
+        self.globals = prev_globals;
+        result?;
+
+        let module = Rc::new(FxModule::new(path.to_string(), module_globals, exports));
+        self.modules.insert(full_path, Rc::clone(&module));
+        Ok(module)
+    }
+
+    //Lexes, parses and resolves a source against this interpreter, ready for `interpret`
+    pub fn load(&mut self, name: &str, src: String) -> Result<Vec<Stmt>, Error> {
+        let source = self.sources.len();
+        self.sources.push(Source { name: name.to_string(), text: src.clone() });
+        let tokens = Lexer::with_source(src, source).scan_tokens()?;
+        let statements = Parser::new(tokens).parse()?;
+        let mut resolver = Resolver::new(self);
+        resolver.resolve_stmts(&statements);
+        if !resolver.diagnostics.is_empty() {
+            return Err(Error::Resolve(resolver.diagnostics));
         }
+        Ok(statements)
     }
 
-    pub fn interpret(&mut self, statements: &Vec<Stmt>) -> Result<(), Error> {
+    //Everything loaded so far, for rendering diagnostics
+    pub fn sources(&self) -> &[Source] {
+        &self.sources
+    }
+
+    pub fn interpret(&mut self, statements: &[Stmt]) -> Result<(), Error> {
         for stmt in statements {
-            self.execute(stmt)?;
+            if let Err(e) = self.execute(stmt) {
+                self.note_traceback(&e, None);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    //Tree-walked calls recurse on the Rust stack, raise this only on a thread with room for it
+    pub fn set_max_stack(&mut self, bytes: usize) {
+        self.max_stack = bytes;
+    }
+
+    //Statements a run may start before it is aborted, the VM counts the same ones. None for no limit
+    pub fn set_step_budget(&mut self, steps: Option<u64>) {
+        self.step_budget = steps;
+    }
+
+    //Wall-clock time a run may take, counted from start_run
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    //Setting the flag from any thread aborts the script, it stays set until the host clears it
+    pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.cancelled)
+    }
+
+    //Starts a new run for the step budget and the timeout
+    pub fn start_run(&mut self) {
+        self.steps = 0;
+        self.stack_base = Some(stack_address());
+        self.deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub(crate) fn tick(&mut self) -> Result<(), Error> {
+        self.steps += 1;
+        if let Some(budget) = self.step_budget {
+            if self.steps > budget {
+                return Err(Error::Aborted(Abort::StepBudget(budget)));
+            }
+        }
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Err(Error::Aborted(Abort::Cancelled));
+        }
+        //Reading the clock every statement would dominate tight loops
+        if let (Some(deadline), Some(timeout)) = (self.deadline, self.timeout) {
+            if self.steps.is_multiple_of(DEADLINE_CHECK_INTERVAL) && Instant::now() >= deadline {
+                return Err(Error::Aborted(Abort::Deadline(timeout)));
+            }
         }
         Ok(())
     }
 
-    pub fn exec_block(&mut self, statements: &Vec<Stmt>, environment: Rc<RefCell<Environment>>) -> Result<(), Error> {
+    pub fn call_stack(&self) -> &[Frame] {
+        &self.call_stack
+    }
+
+    //Where the last uncaught runtime error came from
+    pub fn traceback(&self) -> Option<&Traceback> {
+        self.traceback.as_ref()
+    }
+
+    pub fn clear_traceback(&mut self) {
+        self.traceback = None;
+    }
+
+    //A finally block runs with the pending error's traceback set aside
+    pub(crate) fn take_traceback(&mut self) -> Option<Traceback> {
+        self.traceback.take()
+    }
+
+    pub(crate) fn restore_traceback(&mut self, traceback: Option<Traceback>) {
+        self.traceback = traceback;
+    }
+
+    pub(crate) fn push_frame(&mut self, frame: Frame) {
+        self.call_stack.push(frame);
+    }
+
+    pub(crate) fn truncate_call_stack(&mut self, depth: usize) {
+        self.call_stack.truncate(depth);
+    }
+
+    //Why a call from here would overflow, checked by both backends before every call
+    pub(crate) fn call_overflow(&mut self, name: &str) -> Option<String> {
+        if self.call_stack.len() >= self.max_call_depth {
+            return Some(format!("Stack overflow: call depth exceeded {} calling '{}'.", self.max_call_depth, name));
+        }
+        let here = stack_address();
+        let used = self.stack_base.get_or_insert(here).saturating_sub(here);
+        if used > self.max_stack {
+            return Some(format!("Stack overflow: out of stack space calling '{}'.", name));
+        }
+        None
+    }
+
+    pub(crate) fn export(&mut self, name: &str) {
+        self.exports.push(name.to_string());
+    }
+
+    //Snapshots the stack the first time an error passes, deeper frames are gone by the next
+    pub(crate) fn note_traceback(&mut self, error: &Error, call_line: Option<i32>) {
+        if self.traceback.is_some() {
+            return;
+        }
+        if let Some(line) = error.line().or(call_line) {
+            self.traceback = Some(Traceback { line, frames: self.call_stack.clone() });
+        }
+    }
+
+    //Calls a function or instantiates a class, also the way natives call back into fx
+    pub fn call_value(&mut self, callee: &FxUnit, args: &[FxUnit]) -> Result<FxUnit, Error> {
+        match callee {
+            FxUnit::Callable(func) => {
+                Interpreter::check_arity(func.arity(), args.len())?;
+                func.call(self, args)
+            },
+            FxUnit::Class(class) => {
+                let instance = FxClassInstance::new(class);
+                let init = class.borrow().find_method("init");
+                if let Some(init) = init {
+                    Interpreter::check_arity(init.arity(), args.len())?;
+                    init.bind(instance.clone()).call(self, args)?;
+                }
+                Ok(instance)
+            },
+            _ => Err(Error::Native { message: "Can only call funcs and classes.".to_string() })
+        }
+    }
+
+    //A call made by the embedder, it shows in tracebacks as called from the host
+    pub fn call_from_host(&mut self, name: &str, callee: &FxUnit, args: &[FxUnit]) -> Result<FxUnit, Error> {
+        self.call_stack.push(Frame { name: name.to_string(), line: HOST_LINE });
+        let result = self.call_value(callee, args);
+        if let Err(ref e) = result {
+            self.note_traceback(e, None);
+        }
+        self.call_stack.pop();
+        result
+    }
+
+    pub(crate) fn check_arity(arity: Arity, count: usize) -> Result<(), Error> {
+        if arity.accepts(count) {
+            Ok(())
+        } else {
+            Err(Error::Native { message: format!("Expected {} args but found {}.", arity, count) })
+        }
+    }
+
+    pub fn exec_block(&mut self, statements: &[Stmt], environment: Rc<RefCell<Environment>>) -> Result<Flow, Error> {
         //Stores the previous env
         let prev = self.env.clone();
-        //Exec the block statement
-        let steps = || -> Result<(),Error>{
+        //Exec the block statement, stopping early when a statement unwinds
+        let steps = || -> Result<Flow,Error>{
             self.env = environment;
             for stmt in statements {
-                self.execute(stmt)?
+                match self.execute(stmt)? {
+                    Flow::Normal => (),
+                    other => return Ok(other)
+                }
             }
-            Ok(())
+            Ok(Flow::Normal)
         };
         //Restore the prev env for global vars
         let res = steps();
@@ -98,18 +442,19 @@ impl Interpreter {
         res
     }
 
-    fn execute(&mut self, statement: &Stmt) -> Result<(), Error> {
+    fn execute(&mut self, statement: &Stmt) -> Result<Flow, Error> {
+        self.tick()?;
         statement.accept(self)
     }
 
-    fn evaluate(&mut self, expression: &Expr) -> Result<FxUnit, Error> {
+    pub(crate) fn evaluate(&mut self, expression: &Expr) -> Result<FxUnit, Error> {
         expression.accept(self)
     }
 
     fn is_truthy(&self, fxunit: &FxUnit) -> bool {
         match fxunit {
             FxUnit::Nil => false,
-            FxUnit::Boolean(b) => b.clone(),
+            FxUnit::Boolean(b) => *b,
             _ => true,
         }
     }
@@ -123,37 +468,110 @@ impl Interpreter {
     }
 
     fn stringify(&self, fxunit: FxUnit) -> String {
-        match fxunit {
-            FxUnit::Boolean(b) => b.to_string(),
-            FxUnit::Callable(f) => f.to_string(),
-            FxUnit::Nil => "NIL".to_string(),
-            FxUnit::Number(n) => n.to_string(),
-            FxUnit::Class(c) => c.borrow().name.clone(),
-            FxUnit::Instance(i) => format!("{} instance", i.borrow().class.borrow().name),
-            FxUnit::String(s) => s
+        fxunit.to_string()
+    }
+
+    fn list_index(&self, bracket: &Token, index: &FxUnit, len: usize) -> Result<usize, Error> {
+        match index {
+            FxUnit::Number(n) if n.fract() == 0.0 => {
+                if *n >= 0.0 && (*n as usize) < len {
+                    Ok(*n as usize)
+                } else {
+                    Err(Error::Runtime { token: bracket.clone(), message: format!("List index {} out of range for length {}.", n, len) })
+                }
+            },
+            _ => Err(Error::Runtime { token: bracket.clone(), message: "List index must be an integer.".to_string() })
         }
     }
 
-    pub fn resolve(&mut self, name:&Token,depth:usize){
-        self.locals.insert(name.clone(), depth);
+    //The value a catch block binds, None for errors scripts cannot recover from
+    pub(crate) fn caught_value(&self, error: &Error) -> Option<FxUnit> {
+        match error {
+            Error::Throw { value, .. } => Some((**value).clone()),
+            Error::Runtime { token, message } => {
+                let mut fields = HashMap::new();
+                fields.insert("message".to_string(), FxUnit::String(message.clone()));
+                fields.insert("line".to_string(), FxUnit::Number(token.line as f64));
+                Some(FxClassInstance::with_fields(&self.error_class, fields))
+            },
+            _ => None
+        }
+    }
+
+    pub(crate) fn map_key(&self, token: &Token, key: &FxUnit) -> Result<MapKey, Error> {
+        MapKey::from_unit(key).ok_or_else(|| Error::Runtime {
+            token: token.clone(),
+            message: "Map keys must be strings, numbers or booleans.".to_string()
+        })
+    }
+
+    pub(crate) fn index(&self, bracket: &Token, object: &FxUnit, index: &FxUnit) -> Result<FxUnit, Error> {
+        match object {
+            FxUnit::List(ref elements) => {
+                let elements = elements.borrow();
+                let i = self.list_index(bracket, index, elements.len())?;
+                Ok(elements[i].clone())
+            },
+            FxUnit::Map(ref entries) => {
+                let key = self.map_key(bracket, index)?;
+                entries.borrow().get(&key).cloned().ok_or_else(|| Error::Runtime {
+                    token: bracket.clone(),
+                    message: format!("Key {} not found in map.", key)
+                })
+            },
+            _ => Err(Error::Runtime {
+                token: bracket.clone(),
+                message: "Only lists and maps can be indexed.".to_string()
+            })
+        }
+    }
+
+    pub(crate) fn set_index(&self, bracket: &Token, object: &FxUnit, index: &FxUnit, value: FxUnit) -> Result<FxUnit, Error> {
+        match object {
+            FxUnit::List(ref elements) => {
+                let mut elements = elements.borrow_mut();
+                let i = self.list_index(bracket, index, elements.len())?;
+                elements[i] = value.clone();
+                Ok(value)
+            },
+            FxUnit::Map(ref entries) => {
+                let key = self.map_key(bracket, index)?;
+                entries.borrow_mut().insert(key, value.clone());
+                Ok(value)
+            },
+            _ => Err(Error::Runtime {
+                token: bracket.clone(),
+                message: "Only lists and maps can be indexed.".to_string()
+            })
+        }
+    }
+
+    pub fn resolve(&mut self, id:NodeId, depth:usize, slot:usize){
+        self.locals.insert(id, (depth, slot));
     }
 
-    fn look_up_var(&self, name:&Token)->Result<FxUnit,Error>{
-        if let Some(dist) = self.locals.get(name){
-            self.env.borrow().get_at(*dist,&name.lexeme)
+    fn look_up_var(&self, id:NodeId, name:&Token)->Result<FxUnit,Error>{
+        if let Some(&(depth, slot)) = self.locals.get(&id){
+            Ok(self.env.borrow().get_at(depth, slot))
         }else{
-            self.globals.borrow().get(name)
+            self.env.borrow().get_global(name)
         }
     }
 }
 
+//Roughly where the Rust stack is, it grows down so the distance between two is the stack used
+fn stack_address() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
 impl expr::Visitor<FxUnit> for Interpreter {
-    fn visit_assign_expr(&mut self, name: &Token, val: &Expr) -> Result<FxUnit, Error> {
+    fn visit_assign_expr(&mut self, id: NodeId, name: &Token, val: &Expr) -> Result<FxUnit, Error> {
         let value = self.evaluate(val)?;
-        if let Some(dist) = self.locals.get(name){
-            self.env.borrow_mut().assign_at(*dist, name, value.clone())?;
+        if let Some(&(depth, slot)) = self.locals.get(&id){
+            self.env.borrow_mut().assign_at(depth, slot, value.clone());
         }else{
-            self.env.borrow_mut().assign(name, value.clone())?;
+            self.env.borrow_mut().assign_global(name, value.clone())?;
         }
         Ok(value)
     }
@@ -201,45 +619,38 @@ impl expr::Visitor<FxUnit> for Interpreter {
         }
     }
 
-    fn visit_call_expr(&mut self, callee: &Expr, paren: &Token, arguments: &Vec<Expr>) -> Result<FxUnit, Error> {
+    fn visit_call_expr(&mut self, callee: &Expr, paren: &Token, arguments: &[Expr]) -> Result<FxUnit, Error> {
         let callee = self.evaluate(callee)?;
-        let args_vals:Result<Vec<FxUnit>,Error> = arguments.into_iter().map(|expr| self.evaluate(expr)).collect();
+        let args_vals:Result<Vec<FxUnit>,Error> = arguments.iter().map(|expr| self.evaluate(expr)).collect();
         let args = args_vals?;
-        match callee {
-            FxUnit::Callable(func) => {
-                let args_size = args.len();
-                if args_size!=func.arity() {
-                    Err(Error::Runtime {
-                        token:paren.clone(),
-                        message:format!("Expected {} args but found {}.", func.arity(),args_size)
-                    })
-                }else{
-                    func.call(self,&args)
-                }
-            },
-            FxUnit::Class(ref class )=> {
-                let args_size = args.len();
-                let instance = FxClassInstance::new(class);
-                if let Some(init) = class.borrow().find_method("init"){
-                    if args_size!=init.arity(){
-                        return Err(Error::Runtime {
-                            token:paren.clone(),
-                            message:format!("Expected {} args but found {}.", init.arity(),args_size)
-                        })
-                    }else{
-                        init.bind(instance.clone()).call(self,&args)?;
-                    }
-                }
-                Ok(instance)
-            }
-            _ => Err(Error::Runtime {token:paren.clone(), message:"Can only call funcs and classes.".to_string()})
+        let name = match &callee {
+            FxUnit::Callable(func) => func.name(),
+            FxUnit::Class(class) => class.borrow().name.clone(),
+            other => other.to_string()
+        };
+        if let Some(message) = self.call_overflow(&name) {
+            let error = Error::Runtime { token: paren.clone(), message };
+            self.note_traceback(&error, None);
+            return Err(error);
+        }
+        self.call_stack.push(Frame { name, line: paren.line });
+        let result = self.call_value(&callee, &args).map_err(|e| match e {
+            Error::Native { message } => Error::Runtime { token: paren.clone(), message },
+            other => other
+        });
+        if let Err(ref e) = result {
+            self.note_traceback(e, Some(paren.line));
         }
+        self.call_stack.pop();
+        result
     }
 
     fn visit_get_expr(&mut self, object: &Expr, name: &Token) -> Result<FxUnit, Error> {
         let object = self.evaluate(object)?;
         if let FxUnit::Instance(ref ins) = object {
             ins.borrow().get(name, &object)
+        }else if let FxUnit::Module(ref module) = object {
+            module.get(name)
         }else{
             Err(Error::Runtime {
                 token:name.clone(),
@@ -263,10 +674,11 @@ impl expr::Visitor<FxUnit> for Interpreter {
         }
     }
 
-    fn visit_super_expr(&mut self, keyword: &Token, method: &Token) -> Result<FxUnit, Error> {
-        let dist= self.locals.get(keyword).expect("No Local distance for 'super'");
-        let superclass = self.env.borrow().get_at(*dist, "super")?;
-        let instance = self.env.borrow().get_at(*dist-1, "this")?;
+    fn visit_super_expr(&mut self, id: NodeId, _keyword: &Token, method: &Token) -> Result<FxUnit, Error> {
+        let &(depth, slot) = self.locals.get(&id).expect("No Local distance for 'super'");
+        let superclass = self.env.borrow().get_at(depth, slot);
+        //`this` is the only local of the scope just inside `super`'s
+        let instance = self.env.borrow().get_at(depth - 1, 0);
         if let FxUnit::Class(ref superclass) = superclass {
             if let Some(method) = superclass.borrow().find_method(&method.lexeme){
                 Ok(FxUnit::Callable(method.bind(instance)))
@@ -281,19 +693,58 @@ impl expr::Visitor<FxUnit> for Interpreter {
         }
     }
 
-    fn visit_this_expr(&mut self, keyword: &Token) -> Result<FxUnit, Error> {
-        self.look_up_var(keyword)
+    fn visit_this_expr(&mut self, id: NodeId, keyword: &Token) -> Result<FxUnit, Error> {
+        self.look_up_var(id, keyword)
     }
 
     fn visit_grouping_expr(&mut self, expr: &Expr) -> Result<FxUnit, Error> {
         self.evaluate(expr)
     }
 
+    fn visit_lambda_expr(&mut self, keyword: &Token, params: &[Token], body: &[Stmt]) -> Result<FxUnit, Error> {
+        Ok(FxUnit::Callable(FxFx::User {
+            name: Token::spanned(TokenType::Identifier, "lambda", keyword.line, keyword.span),
+            params: params.to_vec(),
+            body: body.to_vec(),
+            closure: Rc::clone(&self.env),
+            is_init: false
+        }))
+    }
+
+    fn visit_list_expr(&mut self, _bracket: &Token, elements: &[Expr]) -> Result<FxUnit, Error> {
+        let values: Result<Vec<FxUnit>, Error> = elements.iter().map(|expr| self.evaluate(expr)).collect();
+        Ok(FxUnit::list(values?))
+    }
+
+    fn visit_map_expr(&mut self, brace: &Token, entries: &[(Expr, Expr)]) -> Result<FxUnit, Error> {
+        let mut map = BTreeMap::new();
+        for (key, value) in entries {
+            let key = self.evaluate(key)?;
+            let key = self.map_key(brace, &key)?;
+            let value = self.evaluate(value)?;
+            map.insert(key, value);
+        }
+        Ok(FxUnit::map(map))
+    }
+
+    fn visit_index_expr(&mut self, object: &Expr, bracket: &Token, index: &Expr) -> Result<FxUnit, Error> {
+        let object = self.evaluate(object)?;
+        let index = self.evaluate(index)?;
+        self.index(bracket, &object, &index)
+    }
+
+    fn visit_set_index_expr(&mut self, object: &Expr, bracket: &Token, index: &Expr, value: &Expr) -> Result<FxUnit, Error> {
+        let object = self.evaluate(object)?;
+        let index = self.evaluate(index)?;
+        let value = self.evaluate(value)?;
+        self.set_index(bracket, &object, &index, value)
+    }
+
     fn visit_literal_expr(&mut self, val: &LiteralValue) -> Result<FxUnit, Error> {
         match val {
-            LiteralValue::Boolean(b) => Ok(FxUnit::Boolean(b.clone())),
+            LiteralValue::Boolean(b) => Ok(FxUnit::Boolean(*b)),
             LiteralValue::Nil => Ok(FxUnit::Nil),
-            LiteralValue::Number(n) => Ok(FxUnit::Number(n.clone())),
+            LiteralValue::Number(n) => Ok(FxUnit::Number(*n)),
             LiteralValue::String(s) => Ok(FxUnit::String(s.clone()))
         }
     }
@@ -316,7 +767,7 @@ impl expr::Visitor<FxUnit> for Interpreter {
         let right = self.evaluate(rhs)?;
         match &op.token_type {
             TokenType::Minus => match right {
-                FxUnit::Number(n) => Ok(FxUnit::Number(-n.clone())),
+                FxUnit::Number(n) => Ok(FxUnit::Number(-n)),
                 _ => self.num_op_error(op)
             },
             TokenType::Bang => Ok(FxUnit::Boolean(!self.is_truthy(&right))),
@@ -324,78 +775,135 @@ impl expr::Visitor<FxUnit> for Interpreter {
         }
     }
 
-    fn visit_variable_expr(&mut self, name: &Token) -> Result<FxUnit, Error> {
-        self.look_up_var(name)
+    fn visit_variable_expr(&mut self, id: NodeId, name: &Token) -> Result<FxUnit, Error> {
+        self.look_up_var(id, name)
     }
 }
 
-impl stmt::Visitor<()> for Interpreter {
-    fn visit_block_stmt(&mut self, statements: &Vec<Stmt>) -> Result<(), Error> {
+impl stmt::Visitor<Flow> for Interpreter {
+    fn visit_block_stmt(&mut self, statements: &[Stmt]) -> Result<Flow, Error> {
         self.exec_block(
             statements,
             Rc::new(RefCell::new(Environment::from(&self.env))),
-        );
-        Ok(())
+        )
     }
 
-    fn visit_expression_stmt(&mut self, expr: &Expr) -> Result<(), Error> {
+    fn visit_expression_stmt(&mut self, expr: &Expr) -> Result<Flow, Error> {
         self.evaluate(expr)?;
-        Ok(())
+        Ok(Flow::Normal)
     }
 
-    fn visit_print_stmt(&mut self, expr: &Expr) -> Result<(), Error> {
+    fn visit_print_stmt(&mut self, expr: &Expr) -> Result<Flow, Error> {
         let val = self.evaluate(expr)?;
-        println!("{}", self.stringify(val));
-        Ok(())
+        let text = self.stringify(val);
+        writeln!(self.output, "{}", text)?;
+        Ok(Flow::Normal)
     }
 
-    fn visit_var_stmt(&mut self, name: &Token, initializer: &Option<Expr>) -> Result<(), Error> {
+    fn visit_var_stmt(&mut self, name: &Token, initializer: &Option<Expr>) -> Result<Flow, Error> {
         let value: FxUnit = initializer.as_ref().map(|i| self.evaluate(i)).unwrap_or(Ok(FxUnit::Nil))?;
         self.env.borrow_mut().define(name.lexeme.clone(), value);
-        Ok(())
+        Ok(Flow::Normal)
     }
 
-    fn visit_if_stmt(&mut self, condition: &Expr, else_branch: &Option<Stmt>, then_branch: &Stmt) -> Result<(), Error> {
+    fn visit_if_stmt(&mut self, condition: &Expr, else_branch: &Option<Stmt>, then_branch: &Stmt) -> Result<Flow, Error> {
         let cond = self.evaluate(condition)?;
         if self.is_truthy(&cond){
-            self.execute(then_branch)?;
+            self.execute(then_branch)
         }else if let Some(other) = else_branch{
-            self.execute(other)?;
+            self.execute(other)
+        }else{
+            Ok(Flow::Normal)
         }
-        Ok(())
     }
 
-    fn visit_while_stmt(&mut self, condition: &Expr, statement: &Stmt) -> Result<(), Error> {
+    fn visit_while_stmt(&mut self, condition: &Expr, statement: &Stmt, increment: &Option<Expr>) -> Result<Flow, Error> {
         let mut cond = self.evaluate(condition)?;
         while self.is_truthy(&cond) {
-            self.execute(statement)?;
+            match self.execute(statement)? {
+                Flow::Normal | Flow::Continue => (),
+                Flow::Break => break,
+                Flow::Return(value) => return Ok(Flow::Return(value))
+            }
+            if let Some(increment) = increment {
+                self.evaluate(increment)?;
+            }
             cond = self.evaluate(condition)?;
         }
-        Ok(())
+        Ok(Flow::Normal)
+    }
+
+    fn visit_break_stmt(&mut self, _keyword: &Token) -> Result<Flow, Error> {
+        Ok(Flow::Break)
+    }
+
+    fn visit_continue_stmt(&mut self, _keyword: &Token) -> Result<Flow, Error> {
+        Ok(Flow::Continue)
+    }
+
+    fn visit_throw_stmt(&mut self, keyword: &Token, value: &Expr) -> Result<Flow, Error> {
+        let value = self.evaluate(value)?;
+        Err(Error::Throw { token: keyword.clone(), value: Box::new(value) })
+    }
+
+    fn visit_try_stmt(&mut self, body: &[Stmt], catch: &Option<(Token, Vec<Stmt>)>, finally: &Option<Vec<Stmt>>) -> Result<Flow, Error> {
+        let mut result = self.exec_block(body, Rc::new(RefCell::new(Environment::from(&self.env))));
+        if let (Err(error), Some((name, handler))) = (&result, catch) {
+            if let Some(value) = self.caught_value(error) {
+                self.traceback = None;
+                let env = Rc::new(RefCell::new(Environment::from(&self.env)));
+                env.borrow_mut().define(name.lexeme.clone(), value);
+                result = self.exec_block(handler, env);
+            }
+        }
+        if let Some(finally) = finally {
+            //Unwinding out of the finally block wins over whatever the try or catch produced
+            let pending = self.traceback.take();
+            match self.exec_block(finally, Rc::new(RefCell::new(Environment::from(&self.env))))? {
+                Flow::Normal => self.traceback = pending,
+                other => return Ok(other)
+            }
+        }
+        result
+    }
+
+    fn visit_import_stmt(&mut self, keyword: &Token, path: &str, name: &Token) -> Result<Flow, Error> {
+        let module = self.import_module(keyword, path, Interpreter::interpret)?;
+        self.env.borrow_mut().define(name.lexeme.clone(), FxUnit::Module(module));
+        Ok(Flow::Normal)
     }
 
-    fn visit_func_stmt(&mut self, name: &Token, params: &Vec<Token>, body: &Vec<Stmt>) -> Result<(), Error> {
+    fn visit_export_stmt(&mut self, _keyword: &Token, declaration: &Stmt) -> Result<Flow, Error> {
+        self.execute(declaration)?;
+        match declaration {
+            Stmt::Var { name, .. } | Stmt::FxFx { name, .. } | Stmt::Class { name, .. } => self.exports.push(name.lexeme.clone()),
+            _ => unreachable!()
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn visit_func_stmt(&mut self, name: &Token, params: &[Token], body: &[Stmt]) -> Result<Flow, Error> {
         let func = FxFx::User{
             name:name.clone(),
-            params:params.clone(),
-            body:body.clone(),
+            params:params.to_vec(),
+            body:body.to_vec(),
             closure:Rc::clone(&self.env),
             is_init:false
         };
         self.env.borrow_mut().define(name.lexeme.clone(),FxUnit::Callable(func));
-        Ok(())
+        Ok(Flow::Normal)
     }
 
-    fn visit_return_stmt(&mut self, keyword: &Token, value: &Option<Expr>) -> Result<(), Error> {
+    fn visit_return_stmt(&mut self, _keyword: &Token, value: &Option<Expr>) -> Result<Flow, Error> {
         let return_val = value.as_ref().map(|v| self.evaluate(v)).unwrap_or(Ok(FxUnit::Nil))?;
-        Err(Error::Return {value:return_val})
+        Ok(Flow::Return(return_val))
     }
 
-    fn visit_class_stmt(&mut self, name: &Token, superclass:&Option<Expr>, methods: &Vec<Stmt>) -> Result<(), Error> {
+    fn visit_class_stmt(&mut self, name: &Token, superclass:&Option<Expr>, methods: &[Stmt]) -> Result<Flow, Error> {
         let s_class:Option<Rc<RefCell<FxClass>>> = superclass.as_ref().map(|expr|{
             if let FxUnit::Class(ref fx_class) = self.evaluate(expr)? {
                 Ok(Rc::clone(fx_class))
-            }else if let Expr::Variable {name} = expr {
+            }else if let Expr::Variable {name, ..} = expr {
                 Err(Error::Runtime {
                     token:name.clone(),
                     message:"Superclass must be a class.".to_string(),
@@ -404,7 +912,6 @@ impl stmt::Visitor<()> for Interpreter {
                 unreachable!()
             }
         }).transpose()?;
-        self.env.borrow_mut().define(name.lexeme.clone(), FxUnit::Nil);
 
         if let Some(ref class) = s_class {
             self.env = Rc::new(RefCell::new(Environment::from(&self.env)));
@@ -413,7 +920,7 @@ impl stmt::Visitor<()> for Interpreter {
 
         let mut class_methods:HashMap<String,FxFx> = HashMap::new();
         for method in methods{
-            if let Stmt::FxFx {name,params,body} = method {
+            if let Stmt::FxFx {name,params,body,..} = method {
                 let function = FxFx::User {
                     name: name.clone(),
                     params: params.clone(),
@@ -437,8 +944,9 @@ impl stmt::Visitor<()> for Interpreter {
             let parent = self.env.borrow().enclosing.clone().expect("Superclass env has no parent.");
             self.env= parent;
         }
-        self.env.borrow_mut().assign(name, class);
-        Ok(())
+        //Methods only read the class name once called, so it can be defined last
+        self.env.borrow_mut().define(name.lexeme.clone(), class);
+        Ok(Flow::Normal)
     }
 }
 
@@ -1,32 +1,62 @@
-use crate::frontend::tokens::{Token, TokenType, KEYWORDS};
-use crate::frontend::error;
+use crate::frontend::tokens::{Span, Token, TokenType, KEYWORDS};
+use crate::frontend::diagnostic::{Diagnostic, Stage};
+use crate::frontend::error::Error;
 
+/*
+    Walks the source once. `start` and `current` are byte offsets, so spans slice the source
+    directly, while columns count chars so they match what an editor shows.
+*/
 pub struct Lexer {
     src:String,
     token:Vec<Token>,
     start:usize,
     current:usize,
-    line:i32
+    line:i32,
+    //Chars consumed on the current line
+    column:usize,
+    //Line and column of the token being scanned, a string may end lines later
+    start_line:i32,
+    start_column:usize,
+    //Index of this source in Interpreter::sources, stamped on every span
+    source:usize,
+    diagnostics:Vec<Diagnostic>
 }
 
 impl Lexer {
     pub fn new(src:String) -> Lexer{
+        Lexer::with_source(src, 0)
+    }
+
+    pub fn with_source(src:String, source:usize) -> Lexer{
         Lexer{
             src,
             token:Vec::new(),
             start:0,
             current:0,
             line:1,
+            column:0,
+            start_line:1,
+            start_column:1,
+            source,
+            diagnostics:Vec::new(),
         }
     }
 
-    pub fn scan_tokens(&mut self) -> &Vec<Token> {
+    //Scans past bad characters and unterminated strings so every one of them is reported
+    pub fn scan_tokens(mut self) -> Result<Vec<Token>, Error> {
         while !self.is_at_end() {
             self.start = self.current;
+            self.start_line = self.line;
+            self.start_column = self.column + 1;
             self.scan_token();
         }
-        self.token.push(Token::new(TokenType::Eof, "", self.line));
-        &self.token
+        let eof = self.span(self.current, self.current, self.column + 1);
+        self.token.push(Token::spanned(TokenType::Eof, "", self.line, eof));
+        if self.diagnostics.is_empty() {
+            Ok(self.token)
+        } else {
+            Err(Error::Lex(self.diagnostics))
+        }
     }
 
     fn scan_token(&mut self) {
@@ -36,6 +66,9 @@ impl Lexer {
             ')' => self.add_token(TokenType::RightParen),
             '{' => self.add_token(TokenType::LeftBrace),
             '}' => self.add_token(TokenType::RightBrace),
+            '[' => self.add_token(TokenType::LeftBracket),
+            ']' => self.add_token(TokenType::RightBracket),
+            ':' => self.add_token(TokenType::Colon),
             ',' => self.add_token(TokenType::Comma),
             '.' => self.add_token(TokenType::Dot),
             '-' => {
@@ -87,26 +120,26 @@ impl Lexer {
                 }
             }
             ' ' | '\r' | '\t' => (), // Ignore whitespace
-            '\n' => self.line += 1,
+            '\n' => self.new_line(),
             '"' => self.string(),
             c => {
-                if c.is_digit(10) {
+                if c.is_ascii_digit() {
                     self.number()
                 } else if c.is_alphabetic() || c == '_' {
                     self.identifier()
                 } else {
-                    error::error(self.line, "Unexpected character.")
+                    self.error("Unexpected character.")
                 }
             }
         };
     }
 
     fn peek(&self) -> char {
-        self.src.chars().nth(self.current).unwrap_or('\0')
+        self.src[self.current..].chars().next().unwrap_or('\0')
     }
 
     fn peek_next(&self) -> char {
-        self.src.chars().nth(self.current+1).unwrap_or('\0')
+        self.src[self.current..].chars().nth(1).unwrap_or('\0')
     }
 
     fn is_at_end(&self) -> bool {
@@ -114,14 +147,31 @@ impl Lexer {
     }
 
     fn advance(&mut self) -> char {
-        self.current += 1;
-        let char_vec: Vec<char> = self.src.chars().collect();
-        char_vec[self.current-1]
+        let c = self.peek();
+        self.current += c.len_utf8();
+        self.column += 1;
+        c
     }
 
     fn add_token(&mut self, token_type:TokenType) {
         let txt = self.src.get(self.start..self.current).expect("Source Token Empty");
-        self.token.push(Token::new(token_type, txt, self.line))
+        let span = self.span(self.start, self.current, self.start_column);
+        self.token.push(Token::spanned(token_type, txt, self.start_line, span))
+    }
+
+    fn span(&self, start:usize, end:usize, column:usize) -> Span {
+        Span { start, end, column, source: self.source }
+    }
+
+    fn error(&mut self, message:&str) {
+        let span = self.span(self.start, self.current, self.start_column);
+        self.diagnostics.push(Diagnostic::new(Stage::Lex, message, Some(span)));
+    }
+
+    //Called with the newline already consumed
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.column = 0;
     }
 
     fn identifier(&mut self){
@@ -135,14 +185,14 @@ impl Lexer {
     }
 
     fn number(&mut self) {
-        while self.peek().is_digit(10) {
+        while self.peek().is_ascii_digit() {
             self.advance();
         }
 
-        if self.peek() == '.' && self.peek_next().is_digit(10) {
+        if self.peek() == '.' && self.peek_next().is_ascii_digit() {
             self.advance();
 
-            while self.peek().is_digit(10) {
+            while self.peek().is_ascii_digit() {
                 self.advance();
             }
         }
@@ -158,14 +208,13 @@ impl Lexer {
 
     fn string(&mut self) {
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
+            if self.advance() == '\n' {
+                self.new_line();
             }
-            self.advance();
         }
 
         if self.is_at_end() {
-            error::error(self.line, "Unterminated string.");
+            self.error("Unterminated string.");
             return;
         }
 
@@ -180,20 +229,10 @@ impl Lexer {
     }
 
     fn n_match(&mut self, expected: char) -> bool {
-        if self.is_at_end() {
+        if self.is_at_end() || self.peek() != expected {
             return false;
         }
-        if self
-            .src
-            .chars()
-            .nth(self.current)
-            .expect("Unexpected end of source.")
-            != expected
-        {
-            return false;
-        }
-
-        self.current += 1;
+        self.advance();
         true
     }
 }
@@ -201,24 +240,83 @@ impl Lexer {
 #[cfg(test)]
 mod tests {
     use super::*;
-    //FIXME
+
+    fn scan(src: &str) -> Vec<Token> {
+        Lexer::new(src.to_string()).scan_tokens().unwrap()
+    }
+
     #[test]
     fn default_test() {
-        let input = "var a = 5".to_string();
-        let mut lexer = Lexer::new(input);
-        let expected = vec![
-            Token{ token_type:TokenType::Var, lexeme:"var".to_string(),line:1},
-            // Token{ token_type:TokenType::Identifier{literal:"a".to_string()}, lexeme:"a".to_string(),line:1},
-            Token{ token_type:TokenType::Equal, lexeme:"=".to_string(),line:1},
-            Token{ token_type:TokenType::Number {literal:5.0}, lexeme:"5".to_string(),line:1},
-            Token{ token_type:TokenType::Eof, lexeme:"".to_string(),line:1},
+        let expected = [
+            Token::spanned(TokenType::Var, "var", 1, Span::new(0, 3, 1)),
+            Token::spanned(TokenType::Identifier, "a", 1, Span::new(4, 5, 5)),
+            Token::spanned(TokenType::Equal, "=", 1, Span::new(6, 7, 7)),
+            Token::spanned(TokenType::Number {literal:5.0}, "5", 1, Span::new(8, 9, 9)),
+            Token::spanned(TokenType::Eof, "", 1, Span::new(9, 9, 10)),
         ];
-        let actual = lexer.scan_tokens();
+        let actual = scan("var a = 5");
         assert_eq!(expected.len(), actual.len());
         for i in 0..expected.len(){
-            assert_eq!(expected[i].token_type, actual[i].token_type);
-            assert_eq!(expected[i].lexeme, actual[i].lexeme);
-            assert_eq!(expected[i].line, actual[i].line);
+            assert_eq!(expected[i], actual[i]);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn spans_across_lines() {
+        let tokens = scan("print 1;\n  \"a\nb\" x;");
+        //The string starts on line 2 and keeps that line and column
+        assert_eq!(tokens[3].line, 2);
+        assert_eq!(tokens[3].span, Span::new(11, 16, 3));
+        assert_eq!(tokens[4].line, 3);
+        assert_eq!(tokens[4].span, Span::new(17, 18, 4));
+    }
+
+    #[test]
+    fn unicode_identifiers() {
+        let src = "var größe = 1; print größe + ñ_2;";
+        let tokens = scan(src);
+        assert_eq!(tokens[1].token_type, TokenType::Identifier);
+        assert_eq!(tokens[1].lexeme, "größe");
+        assert_eq!(&src[tokens[1].span.start..tokens[1].span.end], "größe");
+        //Columns count chars, `=` is the 11th char even though it starts at byte 12
+        assert_eq!(tokens[2].span, Span::new(12, 13, 11));
+        assert_eq!(tokens[8].lexeme, "ñ_2");
+    }
+
+    #[test]
+    fn emoji_in_strings() {
+        let tokens = scan("print \"🦀 fx 🎉\"; \"é\nü\" x");
+        assert_eq!(tokens[1].token_type, TokenType::String { literal: "🦀 fx 🎉".to_string() });
+        assert_eq!(tokens[2].span.column, 15);
+        assert_eq!(tokens[3].token_type, TokenType::String { literal: "é\nü".to_string() });
+        assert_eq!((tokens[4].line, tokens[4].span.column), (2, 4));
+    }
+
+    #[test]
+    fn megabyte_inputs() {
+        let statements = "var x = 12.5; // ünïcödé comment\n".repeat(32 * 1024);
+        assert!(statements.len() > 1 << 20);
+        let tokens = scan(&statements);
+        assert_eq!(tokens.len(), 5 * 32 * 1024 + 1);
+        assert_eq!(tokens.last().unwrap().line, 32 * 1024 + 1);
+
+        let body = "🦀".repeat(256 * 1024);
+        let tokens = scan(&format!("\"{}\"", body));
+        assert_eq!(tokens[0].token_type, TokenType::String { literal: body });
+        assert_eq!(tokens[1].span.column, 256 * 1024 + 3);
+    }
+
+    #[test]
+    fn errors_are_collected() {
+        match Lexer::new("1 € 2;\nprint \"open".to_string()).scan_tokens() {
+            Err(Error::Lex(diagnostics)) => {
+                let found: Vec<_> = diagnostics.iter().map(|d| (d.message.as_str(), d.span)).collect();
+                assert_eq!(found, [
+                    ("Unexpected character.", Some(Span::new(2, 5, 3))),
+                    ("Unterminated string.", Some(Span::new(15, 20, 7))),
+                ]);
+            },
+            other => panic!("unexpected {:?}", other)
+        }
+    }
+}
@@ -9,4 +9,11 @@ pub mod stmt;
 pub mod env;
 pub mod fxfx;
 pub mod resolver;
-pub mod fxclass;
\ No newline at end of file
+pub mod fxclass;
+pub mod natives;
+pub mod flow;
+pub mod module;
+pub mod convert;
+pub mod diagnostic;
+pub mod trace;
+pub mod capability;
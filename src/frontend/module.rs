@@ -0,0 +1,34 @@
+use crate::frontend::env::Environment;
+use crate::frontend::fxunit::FxUnit;
+use crate::frontend::tokens::Token;
+use crate::frontend::error::Error;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+/*
+    A loaded .fx file. Exports are read from the module's globals on every access,
+    so `lib.count` sees later assignments made by the module's own functions.
+*/
+#[derive(Debug)]
+pub struct FxModule {
+    pub name: String,
+    globals: Rc<RefCell<Environment>>,
+    exports: Vec<String>,
+}
+
+impl FxModule {
+    pub fn new(name: String, globals: Rc<RefCell<Environment>>, exports: Vec<String>) -> Self {
+        FxModule { name, globals, exports }
+    }
+
+    pub fn get(&self, name: &Token) -> Result<FxUnit, Error> {
+        if self.exports.contains(&name.lexeme) {
+            self.globals.borrow().get(name)
+        } else {
+            Err(Error::Runtime {
+                token: name.clone(),
+                message: format!("Module '{}' does not export '{}'.", self.name, name.lexeme)
+            })
+        }
+    }
+}
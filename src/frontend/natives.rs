@@ -0,0 +1,292 @@
+use crate::frontend::fxunit::{FxUnit, MapKey};
+use crate::frontend::fxfx::{Arity, FxFx};
+use crate::frontend::env::Environment;
+use crate::frontend::interpreter::Interpreter;
+use crate::frontend::error::Error;
+use crate::frontend::capability::{Capabilities, Capability};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::{self, Command};
+use std::rc::Rc;
+use std::{env, fs};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn define<F>(globals: &mut Environment, name: &str, arity: Arity, body: F)
+    where F: Fn(&mut Interpreter, &[FxUnit]) -> Result<FxUnit, Error> + 'static {
+    globals.define(name.to_string(), FxUnit::Callable(FxFx::native(name, arity, body)));
+}
+
+fn fail<T>(message: String) -> Result<T, Error> {
+    Err(Error::Native { message })
+}
+
+fn as_list<'a>(name: &str, unit: &'a FxUnit) -> Result<&'a Rc<RefCell<Vec<FxUnit>>>, Error> {
+    match unit {
+        FxUnit::List(elements) => Ok(elements),
+        other => fail(format!("{}() expects a list but got {}.", name, other))
+    }
+}
+
+fn as_map<'a>(name: &str, unit: &'a FxUnit) -> Result<&'a Rc<RefCell<BTreeMap<MapKey, FxUnit>>>, Error> {
+    match unit {
+        FxUnit::Map(entries) => Ok(entries),
+        other => fail(format!("{}() expects a map but got {}.", name, other))
+    }
+}
+
+fn as_key(name: &str, unit: &FxUnit) -> Result<MapKey, Error> {
+    MapKey::from_unit(unit).map_or_else(|| fail(format!("{}() keys must be strings, numbers or booleans.", name)), Ok)
+}
+
+fn as_index(name: &str, unit: &FxUnit) -> Result<usize, Error> {
+    match unit {
+        FxUnit::Number(n) if n.fract() == 0.0 && *n >= 0.0 => Ok(*n as usize),
+        other => fail(format!("{}() expects a non-negative integer index but got {}.", name, other))
+    }
+}
+
+pub fn define_natives(globals: &mut Environment, capabilities: Capabilities) {
+    for &capability in Capability::ALL.iter() {
+        if capabilities.allows(capability) {
+            define_capability(globals, capability);
+        } else {
+            deny_capability(globals, capability);
+        }
+    }
+    define_list_natives(globals);
+    define_map_natives(globals);
+    define_functional_natives(globals);
+}
+
+fn define_capability(globals: &mut Environment, capability: Capability) {
+    match capability {
+        Capability::Io => define_io_natives(globals),
+        Capability::Time => define_time_natives(globals),
+        Capability::Fs => define_fs_natives(globals),
+        Capability::Process => define_process_natives(globals),
+        Capability::Env => define_env_natives(globals),
+    }
+}
+
+//Denied natives stay defined so scripts get told why instead of 'Undefined variable'
+fn deny_capability(globals: &mut Environment, capability: Capability) {
+    let mut denied = Environment::new();
+    define_capability(&mut denied, capability);
+    for name in denied.names() {
+        let message = format!("{}() needs the '{}' capability, which this interpreter was not given.", name, capability);
+        define(globals, &name, Arity::AtLeast(0), move |_, _| fail(message.clone()));
+    }
+}
+
+pub fn define_time_natives(globals: &mut Environment) {
+    define(globals, "clock", Arity::Exact(0), |_, _| {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_err(|e| Error::Native { message: e.to_string() })?;
+        Ok(FxUnit::Number(now.as_secs_f64()))
+    });
+}
+
+fn read_line(interpreter: &mut Interpreter) -> Result<String, Error> {
+    let mut buffer = String::new();
+    interpreter.input().read_line(&mut buffer)?;
+    Ok(buffer.trim().to_string())
+}
+
+pub fn define_io_natives(globals: &mut Environment) {
+    define(globals, "readNum", Arity::Exact(0), |interpreter, _| {
+        let input = read_line(interpreter)?;
+        match input.parse() {
+            Ok(n) => Ok(FxUnit::Number(n)),
+            Err(_) => fail(format!("readNum() could not read '{}' as a number.", input))
+        }
+    });
+
+    define(globals, "readString", Arity::Exact(0), |interpreter, _| {
+        Ok(FxUnit::String(read_line(interpreter)?))
+    });
+}
+
+fn as_string<'a>(name: &str, unit: &'a FxUnit) -> Result<&'a str, Error> {
+    match unit {
+        FxUnit::String(s) => Ok(s),
+        other => fail(format!("{}() expects a string but got {}.", name, other))
+    }
+}
+
+pub fn define_fs_natives(globals: &mut Environment) {
+    define(globals, "readFile", Arity::Exact(1), |_, args| {
+        let path = as_string("readFile", &args[0])?;
+        fs::read_to_string(path).map(FxUnit::String).or_else(|e| fail(format!("readFile() could not read '{}': {}.", path, e)))
+    });
+
+    define(globals, "writeFile", Arity::Exact(2), |_, args| {
+        let path = as_string("writeFile", &args[0])?;
+        let text = as_string("writeFile", &args[1])?;
+        fs::write(path, text).map(|_| FxUnit::Nil).or_else(|e| fail(format!("writeFile() could not write '{}': {}.", path, e)))
+    });
+
+    define(globals, "fileExists", Arity::Exact(1), |_, args| {
+        Ok(FxUnit::Boolean(Path::new(as_string("fileExists", &args[0])?).exists()))
+    });
+}
+
+pub fn define_process_natives(globals: &mut Environment) {
+    //exec(program, arg, ...) runs to completion and gives back its stdout
+    define(globals, "exec", Arity::AtLeast(1), |_, args| {
+        let program = as_string("exec", &args[0])?;
+        let rest = args[1..].iter().map(|arg| as_string("exec", arg)).collect::<Result<Vec<_>, _>>()?;
+        let output = Command::new(program).args(rest).output()
+            .or_else(|e| fail(format!("exec() could not run '{}': {}.", program, e)))?;
+        if !output.status.success() {
+            return fail(format!("exec() '{}' failed with {}.", program, output.status));
+        }
+        Ok(FxUnit::String(String::from_utf8_lossy(&output.stdout).into_owned()))
+    });
+
+    define(globals, "pid", Arity::Exact(0), |_, _| {
+        Ok(FxUnit::Number(process::id() as f64))
+    });
+
+    //exit(status) unwinds the whole script, finally blocks still run but nothing catches it
+    define(globals, "exit", Arity::Range(0, 1), |_, args| {
+        match args.first() {
+            None => Err(Error::Exit(0)),
+            Some(FxUnit::Number(n)) if n.fract() == 0.0 && (0.0..=255.0).contains(n) => Err(Error::Exit(*n as i32)),
+            Some(other) => fail(format!("exit() expects a status from 0 to 255 but got {}.", other))
+        }
+    });
+}
+
+pub fn define_env_natives(globals: &mut Environment) {
+    //getEnv(name) is nil for unset or non-unicode variables
+    define(globals, "getEnv", Arity::Exact(1), |_, args| {
+        let value = env::var(as_string("getEnv", &args[0])?).ok();
+        Ok(value.map_or(FxUnit::Nil, FxUnit::String))
+    });
+}
+
+pub fn define_list_natives(globals: &mut Environment) {
+    //len(xs) works for lists, maps and strings
+    define(globals, "len", Arity::Exact(1), |_, args| {
+        match &args[0] {
+            FxUnit::List(elements) => Ok(FxUnit::Number(elements.borrow().len() as f64)),
+            FxUnit::Map(entries) => Ok(FxUnit::Number(entries.borrow().len() as f64)),
+            FxUnit::String(s) => Ok(FxUnit::Number(s.chars().count() as f64)),
+            other => fail(format!("len() expects a list, map or string but got {}.", other))
+        }
+    });
+
+    //push(xs, v, ...) appends in place and gives back the new length
+    define(globals, "push", Arity::AtLeast(2), |_, args| {
+        let elements = as_list("push", &args[0])?;
+        elements.borrow_mut().extend(args[1..].iter().cloned());
+        let len = elements.borrow().len();
+        Ok(FxUnit::Number(len as f64))
+    });
+
+    //pop(xs) removes the last element, nil when the list is empty
+    define(globals, "pop", Arity::Exact(1), |_, args| {
+        let elements = as_list("pop", &args[0])?;
+        let last = elements.borrow_mut().pop();
+        Ok(last.unwrap_or(FxUnit::Nil))
+    });
+
+    //slice(xs, start, end) copies [start, end) into a new list, bounds are clamped
+    define(globals, "slice", Arity::Exact(3), |_, args| {
+        let elements = as_list("slice", &args[0])?.borrow();
+        let end = as_index("slice", &args[2])?.min(elements.len());
+        let start = as_index("slice", &args[1])?.min(end);
+        Ok(FxUnit::list(elements[start..end].to_vec()))
+    });
+}
+
+pub fn define_map_natives(globals: &mut Environment) {
+    //keys(m) and values(m) come back as lists in key order
+    define(globals, "keys", Arity::Exact(1), |_, args| {
+        let entries = as_map("keys", &args[0])?.borrow();
+        Ok(FxUnit::list(entries.keys().map(|k| k.to_unit()).collect()))
+    });
+
+    define(globals, "values", Arity::Exact(1), |_, args| {
+        let entries = as_map("values", &args[0])?.borrow();
+        Ok(FxUnit::list(entries.values().cloned().collect()))
+    });
+
+    define(globals, "has", Arity::Exact(2), |_, args| {
+        let entries = as_map("has", &args[0])?;
+        let key = as_key("has", &args[1])?;
+        let found = entries.borrow().contains_key(&key);
+        Ok(FxUnit::Boolean(found))
+    });
+
+    //remove(m, k) gives back the removed value, nil when the key was absent
+    define(globals, "remove", Arity::Exact(2), |_, args| {
+        let entries = as_map("remove", &args[0])?;
+        let key = as_key("remove", &args[1])?;
+        let removed = entries.borrow_mut().remove(&key);
+        Ok(removed.unwrap_or(FxUnit::Nil))
+    });
+}
+
+//Natives that take fx callables, the list is copied first so callbacks may mutate it
+//Stable, stops at the first failed comparison, and gives some order for any comparator where
+//the std sorts may panic on an inconsistent one
+fn merge_sort<F>(mut elements: Vec<FxUnit>, compare: &mut F) -> Result<Vec<FxUnit>, Error>
+    where F: FnMut(&FxUnit, &FxUnit) -> Result<Ordering, Error> {
+    if elements.len() < 2 {
+        return Ok(elements);
+    }
+    let right = elements.split_off(elements.len() / 2);
+    let mut left = merge_sort(elements, compare)?.into_iter().peekable();
+    let mut right = merge_sort(right, compare)?.into_iter().peekable();
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        //Ties take from the left, keeping equal elements in their original order
+        let next = if compare(l, r)? == Ordering::Greater { right.next() } else { left.next() };
+        merged.extend(next);
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+pub fn define_functional_natives(globals: &mut Environment) {
+    define(globals, "map", Arity::Exact(2), |interpreter, args| {
+        let elements = as_list("map", &args[0])?.borrow().clone();
+        let mut mapped = Vec::with_capacity(elements.len());
+        for element in elements {
+            mapped.push(interpreter.call_value(&args[1], &[element])?);
+        }
+        Ok(FxUnit::list(mapped))
+    });
+
+    define(globals, "filter", Arity::Exact(2), |interpreter, args| {
+        let elements = as_list("filter", &args[0])?.borrow().clone();
+        let mut kept = Vec::new();
+        for element in elements {
+            if let FxUnit::Nil | FxUnit::Boolean(false) = interpreter.call_value(&args[1], std::slice::from_ref(&element))? {
+                continue;
+            }
+            kept.push(element);
+        }
+        Ok(FxUnit::list(kept))
+    });
+
+    //sort(xs) orders numbers or strings, sort(xs, cmp) uses cmp(a, b) returning <0, 0 or >0
+    define(globals, "sort", Arity::Range(1, 2), |interpreter, args| {
+        let elements = as_list("sort", &args[0])?.borrow().clone();
+        let sorted = merge_sort(elements, &mut |a, b| match args.get(1) {
+            Some(cmp) => match interpreter.call_value(cmp, &[a.clone(), b.clone()])? {
+                FxUnit::Number(n) => Ok(n.partial_cmp(&0.0).unwrap_or(Ordering::Equal)),
+                other => fail(format!("sort() comparator must return a number but returned {}.", other))
+            },
+            None => match (a, b) {
+                (FxUnit::Number(l), FxUnit::Number(r)) => Ok(l.total_cmp(r)),
+                (FxUnit::String(l), FxUnit::String(r)) => Ok(l.cmp(r)),
+                _ => fail(format!("sort() cannot compare {} with {}.", a, b))
+            }
+        })?;
+        Ok(FxUnit::list(sorted))
+    });
+}
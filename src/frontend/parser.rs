@@ -1,58 +1,118 @@
-use crate::frontend::tokens::{Token, TokenType};
-use crate::frontend::expr::{Expr, LiteralValue};
-use crate::frontend::error::{Error, parser_error};
-use crate::frontend::expr::Expr::Literal;
+use crate::frontend::tokens::{Span, Token, TokenType};
+use crate::frontend::expr::{Expr, LiteralValue, NodeId};
+use crate::frontend::error::Error;
+use crate::frontend::diagnostic::{Diagnostic, Stage};
+use std::mem;
 use crate::frontend::stmt::Stmt;
 
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    //Errors that did not stop the parse, like an invalid assignment target
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Parser {
-        Parser { tokens, current: 0 }
+        Parser { tokens, current: 0, diagnostics: Vec::new() }
     }
 
     pub fn parse(&mut self) -> Result<Vec<Stmt>, Error>{
         let mut statements: Vec<Stmt> = Vec::new();
         while !self.is_at_end() {
-            statements.push(self.declaration()?);
+            if let Some(stmt) = self.declaration() {
+                statements.push(stmt);
+            }
+        }
+        if self.diagnostics.is_empty() {
+            Ok(statements)
+        } else {
+            Err(Error::Parse(mem::take(&mut self.diagnostics)))
+        }
+    }
+
+    //A failed declaration is recorded and skipped, so one run reports every syntax error
+    fn declaration(&mut self) -> Option<Stmt>{
+        match self.try_declaration() {
+            Ok(stmt) => Some(stmt),
+            Err(e) => {
+                self.diagnostics.extend(e.diagnostics());
+                self.sync();
+                None
+            }
         }
-        Ok(statements)
     }
 
-    fn declaration(&mut self) -> Result<Stmt, Error>{
+    fn try_declaration(&mut self) -> Result<Stmt, Error>{
         if self.t_match(&[TokenType::Var]){
             self.var_decl()
         }
-        else if self.t_match(&[TokenType::Fn]){
+        //`fn (` starts a lambda expression rather than a declaration
+        else if self.check(TokenType::Fn) && !self.check_next(TokenType::LeftParen){
+            self.advance();
             self.function("function")
         }
         else if self.t_match(&[TokenType::Class]) {
             self.class_decl()
         }
+        else if self.t_match(&[TokenType::Import]) {
+            self.import_decl()
+        }
+        else if self.t_match(&[TokenType::Export]) {
+            self.export_decl()
+        }
         else{
             self.statement()
         }
     }
 
+    fn import_decl(&mut self) -> Result<Stmt, Error>{
+        let keyword = self.previous().clone();
+        let path = match &self.peek().token_type {
+            TokenType::String {literal} => literal.clone(),
+            _ => return Err(self.error(self.peek(), "Expect module path string after 'import'."))
+        };
+        self.advance();
+        self.consume(TokenType::As, "Expect 'as' after module path.")?;
+        let name = self.consume(TokenType::Identifier, "Expect module name after 'as'.")?;
+        self.consume(TokenType::Semicolon, "Expect ';' after import.")?;
+        let span = self.span_from(keyword.span);
+        Ok(Stmt::Import {keyword, path, name, span})
+    }
+
+    fn export_decl(&mut self) -> Result<Stmt, Error>{
+        let keyword = self.previous().clone();
+        let declaration = if self.t_match(&[TokenType::Var]){
+            self.var_decl()?
+        }else if self.t_match(&[TokenType::Fn]){
+            self.function("function")?
+        }else if self.t_match(&[TokenType::Class]){
+            self.class_decl()?
+        }else{
+            return Err(self.error(self.peek(), "Expect var, fn or class declaration after 'export'."));
+        };
+        let span = self.span_from(keyword.span);
+        Ok(Stmt::Export {keyword, declaration:Box::new(declaration), span})
+    }
+
+    //Called with `var` just matched
     fn var_decl(&mut self) -> Result<Stmt, Error>{
+        let start = self.previous().span;
         let name = self.consume(TokenType::Identifier, "Expect a variable name.")?;
         let init = if self.t_match(&[TokenType::Equal]){
             Some(self.expression()?)
         }else{
             None
         };
-        self.consume(TokenType::Semicolon, "Expect ';' after variable declaration");
-        Ok(Stmt::Var{name:name, initializer:init})
+        self.consume(TokenType::Semicolon, "Expect ';' after variable declaration")?;
+        Ok(Stmt::Var{name, initializer:init, span:self.span_from(start)})
     }
 
     fn statement(&mut self) -> Result<Stmt, Error>{
         if self.t_match(&[TokenType::Print]){
             self.print_statement()
         }
-        else if self.t_match((&[TokenType::Return])) {
+        else if self.t_match(&[TokenType::Return]) {
             self.return_stmt()
         }
         else if self.t_match(&[TokenType::If]) {
@@ -64,9 +124,33 @@ impl Parser {
         else if self.t_match(&[TokenType::For]) {
             self.for_stmt()
         }
+        else if self.t_match(&[TokenType::Throw]) {
+            let keyword = self.previous().clone();
+            let value = self.expression()?;
+            self.consume(TokenType::Semicolon, "Expect ';' after thrown value.")?;
+            let span = self.span_from(keyword.span);
+            Ok(Stmt::Throw {keyword, value, span})
+        }
+        else if self.t_match(&[TokenType::Try]) {
+            self.try_stmt()
+        }
+        else if self.t_match(&[TokenType::Break]) {
+            let keyword = self.previous().clone();
+            self.consume(TokenType::Semicolon, "Expect ';' after 'break'.")?;
+            let span = self.span_from(keyword.span);
+            Ok(Stmt::Break {keyword, span})
+        }
+        else if self.t_match(&[TokenType::Continue]) {
+            let keyword = self.previous().clone();
+            self.consume(TokenType::Semicolon, "Expect ';' after 'continue'.")?;
+            let span = self.span_from(keyword.span);
+            Ok(Stmt::Continue {keyword, span})
+        }
         else if self.t_match(&[TokenType::LeftBrace]) {
+            let start = self.previous().span;
             Ok(Stmt::Block {
-                statements:self.block()?
+                statements:self.block()?,
+                span:self.span_from(start)
             })
         }
         else{
@@ -77,16 +161,19 @@ impl Parser {
     fn block(&mut self)->Result<Vec<Stmt>,Error>{
         let mut statements: Vec<Stmt> = Vec::new();
         while !self.check(TokenType::RightBrace) && !self.is_at_end(){
-            statements.push(self.declaration()?);
+            if let Some(stmt) = self.declaration() {
+                statements.push(stmt);
+            }
         }
         self.consume(TokenType::RightBrace,"Expect '}' after block")?;
         Ok(statements)
     }
 
     fn if_stmt(&mut self)-> Result<Stmt, Error>{
-        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.");
+        let start = self.previous().span;
+        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
         let condition = self.expression()?;
-        self.consume(TokenType::RightParen,"Expect ')' after if condition");
+        self.consume(TokenType::RightParen,"Expect ')' after if condition")?;
         let then_branch = Box::new(self.statement()?);
         let else_branch = if self.t_match(&[TokenType::Else]){
             Box::new(Some(self.statement()?))
@@ -95,10 +182,36 @@ impl Parser {
         };
 
         Ok(Stmt::If {
-            condition, else_branch, then_branch
+            condition, else_branch, then_branch, span:self.span_from(start)
         })
     }
 
+    fn try_stmt(&mut self)->Result<Stmt,Error>{
+        let keyword = self.previous().clone();
+        self.consume(TokenType::LeftBrace, "Expect '{' after 'try'.")?;
+        let body = self.block()?;
+        let catch = if self.t_match(&[TokenType::Catch]){
+            self.consume(TokenType::LeftParen, "Expect '(' after 'catch'.")?;
+            let name = self.consume(TokenType::Identifier, "Expect error variable name.")?;
+            self.consume(TokenType::RightParen, "Expect ')' after error variable.")?;
+            self.consume(TokenType::LeftBrace, "Expect '{' before catch body.")?;
+            Some((name, self.block()?))
+        }else{
+            None
+        };
+        let finally = if self.t_match(&[TokenType::Finally]){
+            self.consume(TokenType::LeftBrace, "Expect '{' after 'finally'.")?;
+            Some(self.block()?)
+        }else{
+            None
+        };
+        if catch.is_none() && finally.is_none() {
+            return Err(self.error(&keyword, "Expect 'catch' or 'finally' after try block."));
+        }
+        let span = self.span_from(keyword.span);
+        Ok(Stmt::Try {body, catch, finally, span})
+    }
+
     fn return_stmt(&mut self)->Result<Stmt,Error>{
         let keyword:Token = self.previous().clone();
         let val= if !self.check(TokenType::Semicolon){
@@ -107,17 +220,29 @@ impl Parser {
             None
         };
         self.consume(TokenType::Semicolon,"Expect ';' after return.")?;
-        Ok(Stmt::Return {keyword,value:val})
+        let span = self.span_from(keyword.span);
+        Ok(Stmt::Return {keyword,value:val,span})
     }
 
     fn function(&mut self, kind:&str)->Result<Stmt, Error>{
+        //Declarations start at `fn`, methods at their name
+        let start = if self.previous().token_type == TokenType::Fn { self.previous().span } else { self.peek().span };
         let name = self.consume(TokenType::Identifier, format!("Expect {} name.", kind).as_str())?;
         self.consume(TokenType::LeftParen, format!("Expect '(' after {} name.", kind).as_str())?;
+        let params = self.params()?;
+        self.consume(TokenType::Gives, "Expected -> after fn declaration")?;
+        self.consume(TokenType::LeftBrace, format!("Expect '{{' before {} body.", kind).as_str())?;
+        let body = self.block()?;
+        Ok(Stmt::FxFx {name,params,body,span:self.span_from(start)})
+    }
+
+    fn params(&mut self)->Result<Vec<Token>, Error>{
         let mut params:Vec<Token> = Vec::new();
         if !self.check(TokenType::RightParen){
             loop {
                 if params.len()>=255 {
-                    self.error(self.peek(), "Cannot have more than 255 params");
+                    let token = self.peek().clone();
+                    self.record(&token, "Cannot have more than 255 params");
                 }
                 params.push(self.consume(TokenType::Identifier, "Expect param name,")?);
                 if !self.t_match(&[TokenType::Comma]){
@@ -126,13 +251,45 @@ impl Parser {
             }
         }
         self.consume(TokenType::RightParen,"Expect ')' after params.")?;
-        self.consume(TokenType::Gives, "Expected -> after fn declaration")?;
-        self.consume(TokenType::LeftBrace, format!("Expect '{{' before {} body.", kind).as_str())?;
-        let body = self.block()?;
-        Ok(Stmt::FxFx {name,params,body})
+        Ok(params)
+    }
+
+    fn lambda(&mut self)->Result<Expr, Error>{
+        let keyword = self.previous().clone();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'fn'.")?;
+        let params = self.params()?;
+        self.consume(TokenType::Gives, "Expected -> after fn params")?;
+        let body = if self.t_match(&[TokenType::LeftBrace]){
+            self.block()?
+        }else{
+            let value = self.expression()?;
+            let span = value.span();
+            vec![Stmt::Return {keyword:keyword.clone(), value:Some(value), span}]
+        };
+        let span = self.span_from(keyword.span);
+        Ok(Expr::Lambda {keyword, params, body, span})
     }
 
     fn class_decl(&mut self)->Result<Stmt,Error>{
+        let start = self.previous().span;
+        let (name, superclass) = match self.class_header() {
+            Ok(header) => header,
+            Err(e) => {
+                //Methods read as statements would only give errors of their own
+                self.skip_class_body();
+                return Err(e);
+            }
+        };
+        let mut methods: Vec<Stmt> = Vec::new();
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            methods.push(self.function("method")?);
+        }
+        self.consume(TokenType::RightBrace,"Expect '}' after class body")?;
+        Ok(Stmt::Class {name, superclass: superclass.map(|name| Expr::Variable {id: NodeId::fresh(), name}),methods,span:self.span_from(start)})
+    }
+
+    //The name and superclass, through the '{' opening the body
+    fn class_header(&mut self)->Result<(Token, Option<Token>),Error>{
         let name = self.consume(TokenType::Identifier, "Expect class name.")?;
         let superclass = if self.t_match(&[TokenType::Less]){
             self.consume(TokenType::Identifier, "Expect superclass name.")?;
@@ -142,24 +299,41 @@ impl Parser {
         };
         self.consume(TokenType::Gives, "Expected -> after class declaration.")?;
         self.consume(TokenType::LeftBrace, "Expect '{' before class body.")?;
-        let mut methods: Vec<Stmt> = Vec::new();
-        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
-            methods.push(self.function("method")?);
+        Ok((name, superclass))
+    }
+
+    //Moves onto the '}' closing the next '{', unless a ';' comes first, for sync to step past
+    fn skip_class_body(&mut self){
+        while !self.check(TokenType::LeftBrace) {
+            if self.is_at_end() || self.check(TokenType::Semicolon) { return; }
+            self.advance();
+        }
+        let mut depth = 0;
+        while !self.is_at_end() {
+            match self.peek().token_type {
+                TokenType::LeftBrace => depth += 1,
+                TokenType::RightBrace => {
+                    depth -= 1;
+                    if depth == 0 { return; }
+                },
+                _ => {}
+            }
+            self.advance();
         }
-        self.consume(TokenType::RightBrace,"Expect '}' after class body")?;
-        Ok(Stmt::Class {name, superclass: superclass.map(|name| Expr::Variable {name}),methods})
     }
 
     fn while_stmt(&mut self)->Result<Stmt,Error>{
-        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.");
+        let start = self.previous().span;
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
         let condition = self.expression()?;
-        self.consume(TokenType::RightParen,"Expect ')' after condition.");
+        self.consume(TokenType::RightParen,"Expect ')' after condition.")?;
         let body = Box::new(self.statement()?);
-        Ok(Stmt::While {condition,statement:body})
+        Ok(Stmt::While {condition,statement:body,increment:None,span:self.span_from(start)})
     }
 
     fn for_stmt(&mut self) -> Result<Stmt,Error>{
-        self.consume(TokenType::LeftParen, "Expect '(' after 'for'");
+        let start = self.previous().span;
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'")?;
         let init = if self.t_match(&[TokenType::Semicolon]){
             None
         }else if self.t_match(&[TokenType::Var]){
@@ -173,7 +347,7 @@ impl Parser {
         }else{
             None
         };
-        self.consume(TokenType::Semicolon,"Expeect ';' after loop condition");
+        self.consume(TokenType::Semicolon,"Expect ';' after loop condition")?;
 
         //TODO sure to name it increment??
         let increment = if !self.check(TokenType::RightParen){
@@ -183,20 +357,19 @@ impl Parser {
         };
         self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
 
-        let mut body = self.statement()?;
-        if let Some(inc) = increment {
-            let inc_stmt = Stmt::Expression {expr:inc};
-            body = Stmt::Block {statements:vec![body, inc_stmt]}
-        }
-
-        body = Stmt::While {
-            condition:condition.unwrap_or(Expr::Literal {val:LiteralValue::Boolean(true)}),
-            statement: Box::new(body)
+        let statement = Box::new(self.statement()?);
+        let span = self.span_from(start);
+        let mut body = Stmt::While {
+            condition:condition.unwrap_or(Expr::Literal {val:LiteralValue::Boolean(true), span:start}),
+            statement,
+            increment,
+            span
         };
 
         if let Some(init_stmt) = init{
             body = Stmt::Block {
-                statements:vec![init_stmt, body]
+                statements:vec![init_stmt, body],
+                span
             }
         }
 
@@ -205,15 +378,22 @@ impl Parser {
     }
 
     fn print_statement(&mut self) -> Result<Stmt, Error>{
+        let start = self.previous().span;
         let value = self.expression()?;
         self.consume(TokenType::Semicolon,"Expect ';' after value")?;
-        Ok(Stmt::Print { expr: value })
+        Ok(Stmt::Print { expr: value, span: self.span_from(start) })
     }
 
     fn expr_statement(&mut self) -> Result<Stmt, Error>{
         let expr = self.expression()?;
         self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
-        Ok(Stmt::Expression { expr: expr })
+        let span = self.span_from(expr.span());
+        Ok(Stmt::Expression { expr, span })
+    }
+
+    //From `start` through the token just consumed
+    fn span_from(&self, start: Span) -> Span {
+        start.to(self.previous().span)
     }
 
     fn t_match(&mut self, token_type: &[TokenType]) -> bool {
@@ -227,12 +407,19 @@ impl Parser {
     }
 
     fn check(&self, t_type: TokenType) -> bool {
-        if(self.is_at_end()) {
+        if self.is_at_end() {
             return false;
         }
         t_type == self.peek().token_type
     }
 
+    fn check_next(&self, t_type: TokenType) -> bool {
+        match self.tokens.get(self.current + 1) {
+            Some(token) => token.token_type == t_type,
+            None => false
+        }
+    }
+
     fn is_at_end(&self) -> bool {
         self.peek().token_type == TokenType::Eof
     }
@@ -242,7 +429,7 @@ impl Parser {
     }
 
     fn advance(&mut self) -> &Token{
-        if(!self.is_at_end()){
+        if !self.is_at_end() {
             self.current+=1;
         }
         self.previous()
@@ -257,16 +444,18 @@ impl Parser {
     }
 
     fn assignment(&mut self) -> Result<Expr, Error>{
-        let mut expr = self.or_()?;
+        let expr = self.or_()?;
         if self.t_match(&[TokenType::Equal]){
             let val = Box::new(self.assignment()?);
-            if let Expr::Variable {name} = expr {
-                return Ok(Expr::Assign { name, val });
+            if let Expr::Variable {name, ..} = expr {
+                return Ok(Expr::Assign { id: NodeId::fresh(), name, val });
             }else if let Expr::Get {object, name} = expr{
                 return Ok(Expr::Set {object,name, value: val});
+            }else if let Expr::Index {object, bracket, index} = expr{
+                return Ok(Expr::SetIndex {object, bracket, index, value: val});
             }
-            let equals = self.previous();
-            self.error(equals, "Invalid assignment target.");
+            let equals = self.previous().clone();
+            self.record(&equals, "Invalid assignment target.");
         }
         Ok(expr)
     }
@@ -307,7 +496,7 @@ impl Parser {
             expr = Expr::Binary {
                 lhs: Box::new(expr),
                 rhs: Box::new(rhs),
-                op: op,
+                op,
             }
         }
         Ok(expr)
@@ -389,6 +578,15 @@ impl Parser {
                     name
                 }
             }
+            else if self.t_match(&[TokenType::LeftBracket]) {
+                let index = self.expression()?;
+                let bracket = self.consume(TokenType::RightBracket, "Expect ']' after index.")?;
+                expr = Expr::Index {
+                    object:Box::new(expr),
+                    bracket,
+                    index:Box::new(index)
+                }
+            }
             else{
                 break;
             }
@@ -403,7 +601,8 @@ impl Parser {
             loop {
                 //Inc/Dec your parameter len here
                 if arguments.len() >= 255 {
-                    self.error(self.peek(), "Cannot have more than 255 arguements.");
+                    let token = self.peek().clone();
+                    self.record(&token, "Cannot have more than 255 arguements.");
                 }
                 arguments.push(self.expression()?);
                 if !self.t_match(&[TokenType::Comma]){
@@ -420,34 +619,81 @@ impl Parser {
     }
 
     fn primary(&mut self)->Result<Expr,Error>{
+        let span = self.peek().span;
         let expr = match &self.peek().token_type {
-            TokenType::False => Expr::Literal { val:LiteralValue::Boolean(false) },
-            TokenType::True => Expr::Literal { val:LiteralValue::Boolean(true) },
-            TokenType::Nil => Expr::Literal {val:LiteralValue::Nil},
-            TokenType::String {literal} => Expr::Literal {val:LiteralValue::String(literal.clone())},
-            TokenType::Number {literal} => Expr::Literal {val:LiteralValue::Number(*literal)},
+            TokenType::False => Expr::Literal { val:LiteralValue::Boolean(false), span },
+            TokenType::True => Expr::Literal { val:LiteralValue::Boolean(true), span },
+            TokenType::Nil => Expr::Literal {val:LiteralValue::Nil, span},
+            TokenType::String {literal} => Expr::Literal {val:LiteralValue::String(literal.clone()), span},
+            TokenType::Number {literal} => Expr::Literal {val:LiteralValue::Number(*literal), span},
             TokenType::LeftParen => {
                 self.advance();
                 let expression = self.expression()?;
                 self.consume(TokenType::RightParen,"Expect ')' after expression")?;
                 return Ok(Expr::Grouping {
-                    expr: Box::new(expression)
+                    expr: Box::new(expression),
+                    span: self.span_from(span)
                 });
             },
+            TokenType::LeftBracket => {
+                self.advance();
+                return self.list();
+            },
+            TokenType::Fn => {
+                self.advance();
+                return self.lambda();
+            },
+            TokenType::LeftBrace => {
+                self.advance();
+                return self.map();
+            },
             TokenType::Super => {
                 let keyword = self.advance().clone();
                 self.consume(TokenType::Dot,"Expect '.' after 'super'")?;
                 let method = self.consume(TokenType::Identifier, "Expect superclass method name.")?;
-                return Ok(Expr::Super {keyword, method});
+                return Ok(Expr::Super {id: NodeId::fresh(), keyword, method});
             },
-            TokenType::This => Expr::This {keyword:self.peek().clone()},
-            TokenType::Identifier => Expr::Variable {name: self.peek().clone()},
+            TokenType::This => Expr::This {id: NodeId::fresh(), keyword:self.peek().clone()},
+            TokenType::Identifier => Expr::Variable {id: NodeId::fresh(), name: self.peek().clone()},
             _ => return Err(self.error(self.peek(),"Expect expression."))
         };
         self.advance();
         Ok(expr)
     }
 
+    fn list(&mut self)->Result<Expr,Error>{
+        let start = self.previous().span;
+        let mut elements:Vec<Expr> = Vec::new();
+        if !self.check(TokenType::RightBracket){
+            loop {
+                elements.push(self.expression()?);
+                if !self.t_match(&[TokenType::Comma]){
+                    break;
+                }
+            }
+        }
+        let bracket = self.consume(TokenType::RightBracket, "Expect ']' after list elements.")?;
+        Ok(Expr::List {bracket, elements, span:self.span_from(start)})
+    }
+
+    fn map(&mut self)->Result<Expr,Error>{
+        let start = self.previous().span;
+        let mut entries:Vec<(Expr,Expr)> = Vec::new();
+        if !self.check(TokenType::RightBrace){
+            loop {
+                let key = self.expression()?;
+                self.consume(TokenType::Colon, "Expect ':' after map key.")?;
+                let value = self.expression()?;
+                entries.push((key, value));
+                if !self.t_match(&[TokenType::Comma]){
+                    break;
+                }
+            }
+        }
+        let brace = self.consume(TokenType::RightBrace, "Expect '}' after map entries.")?;
+        Ok(Expr::Map {brace, entries, span:self.span_from(start)})
+    }
+
     fn consume(&mut self,t_type:TokenType,msg:&str)->Result<Token,Error>{
         if self.check(t_type) {
             Ok(self.advance().clone())
@@ -457,23 +703,32 @@ impl Parser {
     }
 
     fn error(&self, token:&Token, msg:&str)->Error{
-        parser_error(token,msg);
-        Error::Parse
+        Error::Parse(vec![Diagnostic::at(Stage::Parse, token, msg)])
     }
 
+    //For errors the parser can carry on past
+    fn record(&mut self, token:&Token, msg:&str){
+        self.diagnostics.push(Diagnostic::at(Stage::Parse, token, msg));
+    }
+
+    //Skips to the next statement boundary, stopping before a '}' so the block around can close
     fn sync(&mut self){
         self.advance();
         while !self.is_at_end() {
-            if(self.previous().token_type==TokenType::Semicolon) { return; }
+            if self.previous().token_type==TokenType::Semicolon { return; }
             match self.peek().token_type {
-                TokenType::Class
+                TokenType::RightBrace
+                | TokenType::Class
+                | TokenType::Import
+                | TokenType::Export
                 | TokenType::Fn
                 | TokenType::Var
-                | TokenType::Var
                 | TokenType::For
                 | TokenType::If
                 | TokenType::While
                 | TokenType::Print
+                | TokenType::Throw
+                | TokenType::Try
                 | TokenType::Return => return,
                 _ => {}
             }
@@ -482,4 +737,49 @@ impl Parser {
     }
 }
 
-//TODO write unit tests for Parser
\ No newline at end of file
+//TODO write unit tests for Parser
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::lexer::Lexer;
+
+    fn messages(src: &str) -> Vec<(i32, String)> {
+        let tokens = Lexer::new(src.to_string()).scan_tokens().unwrap();
+        match Parser::new(tokens).parse() {
+            Err(Error::Parse(diagnostics)) => diagnostics.iter().map(|d| {
+                let line = src[..d.span.unwrap().start].matches('\n').count() as i32 + 1;
+                (line, d.message.clone())
+            }).collect(),
+            other => panic!("expected parse errors, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn reports_every_error() {
+        let src = "var a = ;\nprint 1 +;\nfn f() -> {\n  var = 2;\n  print 3;\n}\nvar ok = 1;\n(1 = 2);\n";
+        assert_eq!(messages(src), vec![
+            (1, "Expect expression.".to_string()),
+            (2, "Expect expression.".to_string()),
+            (4, "Expect a variable name.".to_string()),
+            (8, "Invalid assignment target.".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn bad_class_header_skips_its_body() {
+        let src = "class A {
+  init(x) -> { this.x = x; }
+  get() -> { return this.x; }
+}
+class < B -> {}
+var ok = 1;
+print ok +;
+";
+        assert_eq!(messages(src), vec![
+            (1, "Expected -> after class declaration.".to_string()),
+            (5, "Expect class name.".to_string()),
+            (7, "Expect expression.".to_string()),
+        ]);
+    }
+}
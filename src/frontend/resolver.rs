@@ -1,8 +1,9 @@
 use crate::frontend::interpreter::Interpreter;
-use crate::frontend::expr::{Visitor, Expr, LiteralValue};
+use crate::frontend::expr::{Expr, LiteralValue, NodeId};
 use crate::frontend::{stmt, expr};
-use crate::frontend::error::{Error, parser_error, report};
-use crate::frontend::tokens::{Token, TokenType};
+use crate::frontend::error::Error;
+use crate::frontend::diagnostic::{Diagnostic, Stage};
+use crate::frontend::tokens::Token;
 use crate::frontend::stmt::Stmt;
 use std::collections::HashMap;
 use std::mem;
@@ -15,6 +16,13 @@ enum FunctionType {
     Initializer
 }
 
+//The slot is the local's position in its scope, the order the interpreter defines them in
+#[derive(Debug, Clone, Copy)]
+struct Local {
+    defined: bool,
+    slot: usize
+}
+
 #[derive(Debug, Clone)]
 enum ClassType {
     None,
@@ -24,29 +32,31 @@ enum ClassType {
 
 pub struct Resolver<'a> {
     interpreter:&'a mut Interpreter,
-    scopes: Vec<HashMap<String, bool>>,
+    scopes: Vec<HashMap<String, Local>>,
     current_func:FunctionType,
     current_class:ClassType,
-    pub had_error:bool
+    loop_depth:usize,
+    pub diagnostics:Vec<Diagnostic>
 }
 
 impl<'a> Resolver<'a> {
     pub fn new(interpreter: &'a mut Interpreter) -> Self {
-        Resolver { interpreter, scopes: Vec::new(), current_func:FunctionType::None, current_class:ClassType::None, had_error:false }
+        Resolver { interpreter, scopes: Vec::new(), current_func:FunctionType::None, current_class:ClassType::None, loop_depth:0, diagnostics:Vec::new() }
     }
 
-    pub fn resolve_stmts(&mut self, statements: &Vec<Stmt>) {
+    pub fn resolve_stmts(&mut self, statements: &[Stmt]) {
         for stmt in statements {
             self.resolve_stmt(stmt);
         }
     }
 
+    //Resolution problems are reported through `error`, the visitors themselves never fail
     fn resolve_stmt(&mut self, stmt: &Stmt) {
-        stmt.accept(self);
+        let _ = stmt.accept(self);
     }
 
     fn resolve_expr(&mut self, expr: &Expr) {
-        expr.accept(self);
+        let _ = expr.accept(self);
     }
 
     fn begin_scope(&mut self) {
@@ -59,12 +69,10 @@ impl<'a> Resolver<'a> {
 
     fn declare(&mut self, name:&Token){
         let mut already_defined:bool = false;
-        match self.scopes.last_mut() {
-            Some(ref mut scope) => {
-                already_defined = scope.contains_key(&name.lexeme);
-                scope.insert(name.lexeme.clone(),false);
-            },
-            None => ()
+        if let Some(scope) = self.scopes.last_mut() {
+            already_defined = scope.contains_key(&name.lexeme);
+            let slot = scope.len();
+            scope.insert(name.lexeme.clone(), Local { defined: false, slot });
         }
         if already_defined{
             self.error(name, "Variable with this name already declared.")
@@ -72,24 +80,24 @@ impl<'a> Resolver<'a> {
     }
 
     fn define(&mut self, name:&Token){
-        match self.scopes.last_mut() {
-            Some(ref mut scope) => {
-                scope.insert(name.lexeme.clone(),true);
-            },
-            None => ()
+        if let Some(local) = self.scopes.last_mut().and_then(|scope| scope.get_mut(&name.lexeme)) {
+            local.defined = true;
         }
     }
 
-    fn resolve_local(&mut self, name:&Token){
+    fn resolve_local(&mut self, id:NodeId, name:&Token){
         for (i,scope) in self.scopes.iter().rev().enumerate() {
-            if scope.contains_key(&name.lexeme){
-                self.interpreter.resolve(name,i);
+            if let Some(local) = scope.get(&name.lexeme){
+                self.interpreter.resolve(id, i, local.slot);
+                return;
             }
         }
     }
 
-    fn resolve_func(&mut self,params: &Vec<Token>, body: &Vec<Stmt>, fx_type: FunctionType ){
+    fn resolve_func(&mut self,params: &[Token], body: &[Stmt], fx_type: FunctionType ){
         let enclosing_func = self.current_func.clone();
+        //A loop around the declaration does not make break legal inside the body
+        let enclosing_loops = mem::replace(&mut self.loop_depth, 0);
         self.current_func = fx_type;
         self.begin_scope();
         for param in params{
@@ -99,54 +107,64 @@ impl<'a> Resolver<'a> {
         self.resolve_stmts(body);
         self.end_scope();
         self.current_func = enclosing_func;
+        self.loop_depth = enclosing_loops;
     }
 
     fn error(&mut self, token:&Token, msg:&str){
-        if token.token_type==TokenType::Eof{
-            report(token.line, " at end", msg);
-        }else{
-            report(token.line, &format!(" at '{}'", token.lexeme), msg);
-        }
-        self.had_error = true;
+        self.diagnostics.push(Diagnostic::at(Stage::Resolve, token, msg));
     }
 
 }
 
 impl<'a> stmt::Visitor<()> for Resolver<'a> {
-    fn visit_block_stmt(&mut self, statements: &Vec<Stmt>) -> Result<(), Error> {
+    fn visit_block_stmt(&mut self, statements: &[Stmt]) -> Result<(), Error> {
         self.begin_scope();
         self.resolve_stmts(statements);
         self.end_scope();
         Ok(())
     }
 
-    fn visit_func_stmt(&mut self, name: &Token, params: &Vec<Token>, body: &Vec<Stmt>) -> Result<(), Error> {
+    fn visit_import_stmt(&mut self, _keyword: &Token, _path: &str, name: &Token) -> Result<(), Error> {
+        self.declare(name);
+        self.define(name);
+        Ok(())
+    }
+
+    fn visit_export_stmt(&mut self, keyword: &Token, declaration: &Stmt) -> Result<(), Error> {
+        if !self.scopes.is_empty() {
+            self.error(keyword, "Can only export top-level declarations.");
+        }
+        self.resolve_stmt(declaration);
+        Ok(())
+    }
+
+    fn visit_func_stmt(&mut self, name: &Token, params: &[Token], body: &[Stmt]) -> Result<(), Error> {
         self.declare(name);
         self.define(name);
         self.resolve_func(params,body, FunctionType::Function);
         Ok(())
     }
 
-    fn visit_class_stmt(&mut self, name: &Token, superclass:&Option<Expr>, methods: &Vec<Stmt>) -> Result<(), Error> {
+    fn visit_class_stmt(&mut self, name: &Token, superclass:&Option<Expr>, methods: &[Stmt]) -> Result<(), Error> {
         let enclosing_class = mem::replace(&mut self.current_class,ClassType::Class);
         self.declare(name);
         self.define(name);
 
-        if let Some(Expr::Variable {name:superclass_name}) = superclass{
+        if let Some(Expr::Variable {id, name:superclass_name}) = superclass{
             if name.lexeme == superclass_name.lexeme {
                 self.error(superclass_name, "A class cannot inherit from itself.");
             }
             self.current_class = ClassType::SubClass;
-            self.resolve_local(superclass_name);
+            self.resolve_local(*id, superclass_name);
             self.begin_scope();
-            self.scopes.last_mut().expect("Scope is empty.").insert("super".to_owned(),true);
+            self.scopes.last_mut().expect("Scope is empty.").insert("super".to_owned(), Local { defined: true, slot: 0 });
         }
 
         self.begin_scope();
-        self.scopes.last_mut().expect("Scope is empty.").insert("this".to_owned(),true);
+        self.scopes.last_mut().expect("Scope is empty.").insert("this".to_owned(), Local { defined: true, slot: 0 });
 
         for method in methods{
-            if let Stmt::FxFx {name,params,body} = method {
+            if let Stmt::FxFx {name,params,body,..} = method {
                 let declaration = if name.lexeme=="init"{
                     FunctionType::Initializer
                 }else{
@@ -187,9 +205,52 @@ impl<'a> stmt::Visitor<()> for Resolver<'a> {
         Ok(())
     }
 
-    fn visit_while_stmt(&mut self, condition: &Expr, statement: &Stmt) -> Result<(), Error> {
+    fn visit_while_stmt(&mut self, condition: &Expr, statement: &Stmt, increment: &Option<Expr>) -> Result<(), Error> {
         self.resolve_expr(condition);
+        self.loop_depth += 1;
         self.resolve_stmt(statement);
+        self.loop_depth -= 1;
+        if let Some(increment) = increment {
+            self.resolve_expr(increment);
+        }
+        Ok(())
+    }
+
+    fn visit_break_stmt(&mut self, keyword: &Token) -> Result<(), Error> {
+        if self.loop_depth == 0 {
+            self.error(keyword, "Cannot use 'break' outside of a loop.");
+        }
+        Ok(())
+    }
+
+    fn visit_continue_stmt(&mut self, keyword: &Token) -> Result<(), Error> {
+        if self.loop_depth == 0 {
+            self.error(keyword, "Cannot use 'continue' outside of a loop.");
+        }
+        Ok(())
+    }
+
+    fn visit_throw_stmt(&mut self, _keyword: &Token, value: &Expr) -> Result<(), Error> {
+        self.resolve_expr(value);
+        Ok(())
+    }
+
+    fn visit_try_stmt(&mut self, body: &[Stmt], catch: &Option<(Token, Vec<Stmt>)>, finally: &Option<Vec<Stmt>>) -> Result<(), Error> {
+        self.begin_scope();
+        self.resolve_stmts(body);
+        self.end_scope();
+        if let Some((name, handler)) = catch {
+            self.begin_scope();
+            self.declare(name);
+            self.define(name);
+            self.resolve_stmts(handler);
+            self.end_scope();
+        }
+        if let Some(finally) = finally {
+            self.begin_scope();
+            self.resolve_stmts(finally);
+            self.end_scope();
+        }
         Ok(())
     }
 
@@ -214,19 +275,19 @@ impl<'a> stmt::Visitor<()> for Resolver<'a> {
 }
 
 impl<'a> expr::Visitor<()> for Resolver<'a>{
-    fn visit_assign_expr(&mut self, name: &Token, val: &Expr) -> Result<(), Error> {
+    fn visit_assign_expr(&mut self, id: NodeId, name: &Token, val: &Expr) -> Result<(), Error> {
         self.resolve_expr(val);
-        self.resolve_local(name);
+        self.resolve_local(id, name);
         Ok(())
     }
 
-    fn visit_binary_expr(&mut self, lhs: &Expr, rhs: &Expr, op: &Token) -> Result<(), Error> {
+    fn visit_binary_expr(&mut self, lhs: &Expr, rhs: &Expr, _op: &Token) -> Result<(), Error> {
         self.resolve_expr(lhs);
         self.resolve_expr(rhs);
         Ok(())
     }
 
-    fn visit_call_expr(&mut self, callee: &Expr, paren: &Token, arguments: &Vec<Expr>) -> Result<(), Error> {
+    fn visit_call_expr(&mut self, callee: &Expr, _paren: &Token, arguments: &[Expr]) -> Result<(), Error> {
         self.resolve_expr(callee);
         for arg in arguments {
             self.resolve_expr(arg);
@@ -234,31 +295,31 @@ impl<'a> expr::Visitor<()> for Resolver<'a>{
         Ok(())
     }
 
-    fn visit_get_expr(&mut self, object: &Expr, name: &Token) -> Result<(), Error> {
+    fn visit_get_expr(&mut self, object: &Expr, _name: &Token) -> Result<(), Error> {
         self.resolve_expr(object);
         Ok(())
     }
 
-    fn visit_set_expr(&mut self, object: &Expr, name: &Token, value: &Expr) -> Result<(),Error>{
+    fn visit_set_expr(&mut self, object: &Expr, _name: &Token, value: &Expr) -> Result<(),Error>{
         self.resolve_expr(value);
         self.resolve_expr(object);
         Ok(())
     }
 
-    fn visit_super_expr(&mut self, keyword: &Token, method: &Token) -> Result<(), Error> {
+    fn visit_super_expr(&mut self, id: NodeId, keyword: &Token, _method: &Token) -> Result<(), Error> {
         match self.current_class {
             ClassType::None => self.error(keyword, "Cannot use super outside of a class."),
             ClassType::Class => self.error(keyword, "Cannot use 'super' in the base class."),
-            _ => self.resolve_local(keyword)
+            _ => self.resolve_local(id, keyword)
         }
         Ok(())
     }
 
-    fn visit_this_expr(&mut self, keyword: &Token) -> Result<(), Error> {
+    fn visit_this_expr(&mut self, id: NodeId, keyword: &Token) -> Result<(), Error> {
         if let ClassType::None = self.current_class {
             self.error(keyword, "Cannot use 'this' outside of class");
         }else{
-            self.resolve_local(keyword);
+            self.resolve_local(id, keyword);
         }
         Ok(())
     }
@@ -268,30 +329,83 @@ impl<'a> expr::Visitor<()> for Resolver<'a>{
         Ok(())
     }
 
-    fn visit_literal_expr(&mut self, val: &LiteralValue) -> Result<(), Error> {
+    fn visit_lambda_expr(&mut self, _keyword: &Token, params: &[Token], body: &[Stmt]) -> Result<(), Error> {
+        self.resolve_func(params, body, FunctionType::Function);
+        Ok(())
+    }
+
+    fn visit_list_expr(&mut self, _bracket: &Token, elements: &[Expr]) -> Result<(), Error> {
+        for element in elements {
+            self.resolve_expr(element);
+        }
+        Ok(())
+    }
+
+    fn visit_map_expr(&mut self, _brace: &Token, entries: &[(Expr, Expr)]) -> Result<(), Error> {
+        for (key, value) in entries {
+            self.resolve_expr(key);
+            self.resolve_expr(value);
+        }
+        Ok(())
+    }
+
+    fn visit_index_expr(&mut self, object: &Expr, _bracket: &Token, index: &Expr) -> Result<(), Error> {
+        self.resolve_expr(object);
+        self.resolve_expr(index);
+        Ok(())
+    }
+
+    fn visit_set_index_expr(&mut self, object: &Expr, _bracket: &Token, index: &Expr, value: &Expr) -> Result<(), Error> {
+        self.resolve_expr(value);
+        self.resolve_expr(object);
+        self.resolve_expr(index);
         Ok(())
     }
 
-    fn visit_logical_expr(&mut self, lhs: &Expr, rhs: &Expr, op: &Token) -> Result<(), Error> {
+    fn visit_literal_expr(&mut self, _val: &LiteralValue) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn visit_logical_expr(&mut self, lhs: &Expr, rhs: &Expr, _op: &Token) -> Result<(), Error> {
         self.resolve_expr(lhs);
         self.resolve_expr(rhs);
         Ok(())
     }
 
-    fn visit_unary_expr(&mut self, op: &Token, rhs: &Expr) -> Result<(), Error> {
+    fn visit_unary_expr(&mut self, _op: &Token, rhs: &Expr) -> Result<(), Error> {
         self.resolve_expr(rhs);
         Ok(())
     }
 
-    fn visit_variable_expr(&mut self, name: &Token) -> Result<(), Error> {
+    fn visit_variable_expr(&mut self, id: NodeId, name: &Token) -> Result<(), Error> {
         if let Some(scope) = self.scopes.last() {
-            if let Some(flag) = scope.get(&name.lexeme){
-                if *flag == false {
+            if let Some(local) = scope.get(&name.lexeme){
+                if !local.defined {
                     self.error(name, "Cannot read local var in its own initializer.");
                 }
             }
         };
-        self.resolve_local(name);
+        self.resolve_local(id, name);
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::lexer::Lexer;
+    use crate::frontend::parser::Parser;
+    use crate::frontend::tokens::TokenType;
+
+    #[test]
+    fn shadowed_names_resolve_to_innermost_scope() {
+        let src = "var seen = [];\n{\n  var a = 1;\n  {\n    var a = 2;\n    push(seen, a);\n  }\n  push(seen, a);\n}\n";
+        let tokens = Lexer::new(src.to_string()).scan_tokens().unwrap();
+        let statements = Parser::new(tokens).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        Resolver::new(&mut interpreter).resolve_stmts(&statements);
+        interpreter.interpret(&statements).unwrap();
+        let seen = interpreter.globals.borrow().get(&Token::new(TokenType::Identifier, "seen", 1)).unwrap();
+        assert_eq!(seen.to_string(), "[2, 1]");
+    }
+}
@@ -1,72 +1,151 @@
 use crate::frontend::expr::Expr;
 use crate::frontend::error::Error;
-use crate::frontend::tokens::Token;
+use crate::frontend::tokens::{Span, Token};
 
+/*
+    Every statement keeps the span it was parsed from, from its first token to its closing
+    `;` or `}`. Desugared nodes, like the while a for loop becomes, take the for's span.
+*/
 #[derive(Debug, Clone)]
 pub enum Stmt {
     FxFx{
         name:Token,
         params:Vec<Token>,
-        body:Vec<Stmt>
+        body:Vec<Stmt>,
+        span:Span
     },
     Return{
         keyword:Token,
-        value:Option<Expr>
+        value:Option<Expr>,
+        span:Span
     },
     Block {
-        statements: Vec<Stmt>
+        statements: Vec<Stmt>,
+        span: Span
+    },
+    Import {
+        keyword: Token,
+        path: String,
+        name: Token,
+        span: Span
+    },
+    //Wraps a top-level var, fn or class declaration
+    Export {
+        keyword: Token,
+        declaration: Box<Stmt>,
+        span: Span
     },
     Class {
         name:Token,
         superclass:Option<Expr>,
-        methods: Vec<Stmt>
+        methods: Vec<Stmt>,
+        span:Span
     },
     If {
         condition: Expr,
         else_branch: Box<Option<Stmt>>,
         then_branch: Box<Stmt>,
+        span: Span
     },
+    //`for` loops keep their increment here so `continue` still runs it
     While {
         condition: Expr,
         statement: Box<Stmt>,
+        increment: Option<Expr>,
+        span: Span
+    },
+    Break {
+        keyword: Token,
+        span: Span
+    },
+    Continue {
+        keyword: Token,
+        span: Span
+    },
+    Throw {
+        keyword: Token,
+        value: Expr,
+        span: Span
+    },
+    Try {
+        body: Vec<Stmt>,
+        catch: Option<(Token, Vec<Stmt>)>,
+        finally: Option<Vec<Stmt>>,
+        span: Span
     },
     Expression {
-        expr: Expr
+        expr: Expr,
+        span: Span
     },
     Var {
         name: Token,
         initializer: Option<Expr>,
+        span: Span
     },
     Print {
-        expr: Expr
+        expr: Expr,
+        span: Span
     },
     Nil,
 }
 
 impl Stmt {
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::FxFx { span, .. }
+            | Stmt::Return { span, .. }
+            | Stmt::Block { span, .. }
+            | Stmt::Import { span, .. }
+            | Stmt::Export { span, .. }
+            | Stmt::Class { span, .. }
+            | Stmt::If { span, .. }
+            | Stmt::While { span, .. }
+            | Stmt::Break { span, .. }
+            | Stmt::Continue { span, .. }
+            | Stmt::Throw { span, .. }
+            | Stmt::Try { span, .. }
+            | Stmt::Expression { span, .. }
+            | Stmt::Var { span, .. }
+            | Stmt::Print { span, .. } => *span,
+            Stmt::Nil => Span::default(),
+        }
+    }
+
     pub fn accept<R>(&self, v: &mut dyn Visitor<R>) -> Result<R, Error> {
         match self {
-            Stmt::Block { statements } => v.visit_block_stmt(statements),
-            Stmt::If { condition, else_branch, then_branch } => v.visit_if_stmt(condition, else_branch, then_branch),
-            Stmt::While { condition, statement } => v.visit_while_stmt(condition, statement),
-            Stmt::Expression { expr: expression } => v.visit_expression_stmt(expression),
-            Stmt::Var { name, initializer } => v.visit_var_stmt(name, initializer),
-            Stmt::Print { expr: expression } => v.visit_print_stmt(expression),
+            Stmt::Block { statements, .. } => v.visit_block_stmt(statements),
+            Stmt::Import { keyword, path, name, .. } => v.visit_import_stmt(keyword, path, name),
+            Stmt::Export { keyword, declaration, .. } => v.visit_export_stmt(keyword, declaration),
+            Stmt::If { condition, else_branch, then_branch, .. } => v.visit_if_stmt(condition, else_branch, then_branch),
+            Stmt::While { condition, statement, increment, .. } => v.visit_while_stmt(condition, statement, increment),
+            Stmt::Break { keyword, .. } => v.visit_break_stmt(keyword),
+            Stmt::Continue { keyword, .. } => v.visit_continue_stmt(keyword),
+            Stmt::Throw { keyword, value, .. } => v.visit_throw_stmt(keyword, value),
+            Stmt::Try { body, catch, finally, .. } => v.visit_try_stmt(body, catch, finally),
+            Stmt::Expression { expr: expression, .. } => v.visit_expression_stmt(expression),
+            Stmt::Var { name, initializer, .. } => v.visit_var_stmt(name, initializer),
+            Stmt::Print { expr: expression, .. } => v.visit_print_stmt(expression),
             Stmt::Nil => unimplemented!(),
-            Stmt::FxFx { name, params, body } => v.visit_func_stmt(name,params,body),
-            Stmt::Return { keyword,value } => v.visit_return_stmt(keyword,value),
-            Stmt::Class {name, superclass, methods} => v.visit_class_stmt(name, superclass, methods)
+            Stmt::FxFx { name, params, body, .. } => v.visit_func_stmt(name,params,body),
+            Stmt::Return { keyword,value, .. } => v.visit_return_stmt(keyword,value),
+            Stmt::Class {name, superclass, methods, .. } => v.visit_class_stmt(name, superclass, methods)
         }
     }
 }
 
 pub trait Visitor<T> {
-    fn visit_block_stmt(&mut self, statements: &Vec<Stmt>) -> Result<T, Error>;
-    fn visit_func_stmt(&mut self, name:&Token, params:&Vec<Token>, body:&Vec<Stmt>)->Result<T,Error>;
-    fn visit_class_stmt(&mut self, name:&Token, superclass:&Option<Expr>,methods:&Vec<Stmt>) -> Result<T, Error>;
+    fn visit_block_stmt(&mut self, statements: &[Stmt]) -> Result<T, Error>;
+    fn visit_import_stmt(&mut self, keyword: &Token, path: &str, name: &Token) -> Result<T, Error>;
+    fn visit_export_stmt(&mut self, keyword: &Token, declaration: &Stmt) -> Result<T, Error>;
+    fn visit_func_stmt(&mut self, name:&Token, params:&[Token], body:&[Stmt])->Result<T,Error>;
+    fn visit_class_stmt(&mut self, name:&Token, superclass:&Option<Expr>,methods:&[Stmt]) -> Result<T, Error>;
     fn visit_return_stmt(&mut self, keyword:&Token , value:&Option<Expr>)->Result<T,Error>;
     fn visit_if_stmt(&mut self, condition: &Expr, else_branch: &Option<Stmt>, then_branch: &Stmt) -> Result<T, Error>;
-    fn visit_while_stmt(&mut self, condition: &Expr, statement: &Stmt) -> Result<T, Error>;
+    fn visit_while_stmt(&mut self, condition: &Expr, statement: &Stmt, increment: &Option<Expr>) -> Result<T, Error>;
+    fn visit_break_stmt(&mut self, keyword: &Token) -> Result<T, Error>;
+    fn visit_continue_stmt(&mut self, keyword: &Token) -> Result<T, Error>;
+    fn visit_throw_stmt(&mut self, keyword: &Token, value: &Expr) -> Result<T, Error>;
+    fn visit_try_stmt(&mut self, body: &[Stmt], catch: &Option<(Token, Vec<Stmt>)>, finally: &Option<Vec<Stmt>>) -> Result<T, Error>;
     fn visit_expression_stmt(&mut self, expr: &Expr) -> Result<T, Error>;
     fn visit_var_stmt(&mut self, name: &Token, initializer: &Option<Expr>) -> Result<T, Error>;
     fn visit_print_stmt(&mut self, expr: &Expr) -> Result<T, Error>;
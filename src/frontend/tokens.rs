@@ -1,7 +1,6 @@
 use std::collections::HashMap;
 use lazy_static::lazy_static;
 use std::fmt;
-use std::hash::{Hasher, Hash};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenType {
@@ -10,6 +9,9 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
     Comma,
     Dot,
     Minus,
@@ -35,20 +37,29 @@ pub enum TokenType {
 
     // Keywords
     And,
+    As,
+    Break,
+    Catch,
     Class,
+    Continue,
     Else,
+    Export,
     False,
+    Finally,
     Fn,
     Gives,
     For,
     If,
+    Import,
     Nil,
     Or,
     Print,
     Return,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
 
@@ -59,19 +70,28 @@ lazy_static! {
     pub static ref KEYWORDS: HashMap<&'static str,TokenType> = {
         let mut map = HashMap::new();
         map.insert("and", TokenType::And);
+        map.insert("as", TokenType::As);
+        map.insert("break", TokenType::Break);
+        map.insert("catch", TokenType::Catch);
         map.insert("class", TokenType::Class);
+        map.insert("continue", TokenType::Continue);
         map.insert("else", TokenType::Else);
+        map.insert("export", TokenType::Export);
         map.insert("false", TokenType::False);
+        map.insert("finally", TokenType::Finally);
         map.insert("for", TokenType::For);
         map.insert("fn", TokenType::Fn);
         map.insert("if", TokenType::If);
+        map.insert("import", TokenType::Import);
         map.insert("nil", TokenType::Nil);
         map.insert("or", TokenType::Or);
         map.insert("print", TokenType::Print);
         map.insert("return", TokenType::Return);
         map.insert("super", TokenType::Super);
         map.insert("this", TokenType::This);
+        map.insert("throw", TokenType::Throw);
         map.insert("true", TokenType::True);
+        map.insert("try", TokenType::Try);
         map.insert("var", TokenType::Var);
         map.insert("while", TokenType::While);
         map.insert("->", TokenType::Gives);
@@ -79,19 +99,56 @@ lazy_static! {
     };
 }
 
+/*
+    Where a token or node came from: the byte range [start, end) of the source, the
+    1-based column of its first character on its line, and which loaded source it is
+    (an index into Interpreter::sources, 0 outside an interpreter). Source::position gives
+    the line and column of either end.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub column: usize,
+    pub source: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, column: usize) -> Span {
+        Span { start, end, column, source: 0 }
+    }
+
+    //The smallest span covering both
+    pub fn to(&self, other: Span) -> Span {
+        let first = if self.start <= other.start { self } else { &other };
+        Span {
+            start: first.start,
+            end: self.end.max(other.end),
+            column: first.column,
+            source: first.source,
+        }
+    }
+}
+
 #[derive(Debug, Clone,PartialEq)]
 pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
     pub line: i32,
+    pub span: Span,
 }
 
 impl Token {
     pub fn new(token_type: TokenType, lexeme: &str, line: i32) -> Token {
+        Token::spanned(token_type, lexeme, line, Span::default())
+    }
+
+    pub fn spanned(token_type: TokenType, lexeme: &str, line: i32, span: Span) -> Token {
         Token {
             token_type,
             lexeme: lexeme.to_string(),
             line,
+            span,
         }
     }
 }
@@ -105,12 +162,3 @@ impl fmt::Display for Token {
         }
     }
 }
-//TODO add docs for hash, hasher and Eq
-impl Hash for Token {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.lexeme.hash(state);
-        self.line.hash(state);
-    }
-}
-
-impl Eq for Token {}
\ No newline at end of file
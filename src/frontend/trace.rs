@@ -0,0 +1,62 @@
+use std::fmt;
+
+//Call-site line of calls made from Rust through Engine::call
+pub const HOST_LINE: i32 = 0;
+
+//One active fx call, `line` is where it was called from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub name: String,
+    pub line: i32,
+}
+
+/*
+    The call stack as it was when a runtime error escaped, innermost call last. Displays
+    from the failing line outwards:
+
+        at fib (line 4)
+        at <script> (line 7)
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Traceback {
+    pub line: i32,
+    pub frames: Vec<Frame>,
+}
+
+//Runs of the same entry longer than this are folded, deep recursion would bury the rest
+const MAX_REPEATS: usize = 3;
+
+impl fmt::Display for Traceback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut line = self.line;
+        let mut previous: Option<(&str, i32)> = None;
+        let mut repeats = 0;
+        for frame in self.frames.iter().rev() {
+            let entry = (frame.name.as_str(), line);
+            if previous == Some(entry) {
+                repeats += 1;
+            } else {
+                fold(f, repeats)?;
+                repeats = 0;
+                previous = Some(entry);
+            }
+            if repeats < MAX_REPEATS {
+                writeln!(f, "at {} (line {})", frame.name, line)?;
+            }
+            line = frame.line;
+        }
+        fold(f, repeats)?;
+        if line == HOST_LINE {
+            writeln!(f, "at <host>")
+        } else {
+            writeln!(f, "at <script> (line {})", line)
+        }
+    }
+}
+
+fn fold(f: &mut fmt::Formatter<'_>, repeats: usize) -> fmt::Result {
+    if repeats >= MAX_REPEATS {
+        writeln!(f, "... previous line repeated {} more times", repeats + 1 - MAX_REPEATS)?;
+    }
+    Ok(())
+}
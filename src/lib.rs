@@ -1 +1,6 @@
-pub mod frontend;
\ No newline at end of file
+pub mod frontend;
+pub mod vm;
+mod engine;
+
+pub use engine::{Backend, Engine};
+pub use frontend::convert::{ConvertError, FromFx, IntoFx};
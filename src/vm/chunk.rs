@@ -0,0 +1,119 @@
+use crate::frontend::fxunit::FxUnit;
+use crate::frontend::tokens::Token;
+use std::rc::Rc;
+
+/*
+    One instruction. Operands index the chunk's constant pool, the frame's locals or its
+    closure's upvalues, jumps are relative to the instruction after them. Every variant
+    fits in four bytes.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Constant(u16),
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal(u16),
+    SetLocal(u16),
+    GetGlobal(u16),
+    DefineGlobal(u16),
+    SetGlobal(u16),
+    GetUpvalue(u16),
+    SetUpvalue(u16),
+    GetProperty(u16),
+    SetProperty(u16),
+    //Pops the superclass and the instance, pushes the bound method
+    GetSuper(u16),
+    GetIndex,
+    SetIndex,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    Jump(u16),
+    //Leaves the condition on the stack, `and`/`or` keep it as their value
+    JumpIfFalse(u16),
+    Loop(u16),
+    Call(u8),
+    Closure(u16),
+    CloseUpvalue,
+    Return,
+    Class(u16),
+    //Pops the subclass, the superclass stays below it as the `super` local
+    Inherit,
+    Method(u16),
+    List(u16),
+    Map(u16),
+    Throw,
+    //Handlers jump to their target when an error unwinds to them, see Vm::unwind
+    PushCatch(u16),
+    PushFinally(u16),
+    PopHandler,
+    //Ends a finally block entered by an error, raising that error again
+    Rethrow,
+    //Leaving such a finally block early drops its error instead
+    DropPending,
+    Import(u16),
+    Export(u16),
+    //Starts a statement, counting it against the run's limits like the tree-walker does
+    Tick,
+}
+
+#[derive(Debug)]
+pub enum Constant {
+    Value(FxUnit),
+    //Global, property and method names
+    Name(String),
+    Function(Rc<Function>),
+}
+
+//Where a closure finds a captured variable: a local of the enclosing frame or one of its upvalues
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpvalueRef {
+    pub is_local: bool,
+    pub index: u16,
+}
+
+/*
+    Compiled code plus what it refers to. `sites` runs parallel to `code` and points each
+    instruction at the token it came from, so errors carry the same token, line and span
+    the tree-walker reports.
+*/
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    pub constants: Vec<Constant>,
+    pub tokens: Vec<Token>,
+    pub sites: Vec<u32>,
+}
+
+impl Chunk {
+    pub fn token_at(&self, ip: usize) -> &Token {
+        &self.tokens[self.sites[ip] as usize]
+    }
+
+    pub fn name(&self, index: u16) -> &str {
+        match self.constants[index as usize] {
+            Constant::Name(ref name) => name,
+            ref other => panic!("Constant {:?} is not a name", other)
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Function {
+    pub name: String,
+    pub arity: usize,
+    pub chunk: Chunk,
+    pub upvalues: Vec<UpvalueRef>,
+}
@@ -0,0 +1,841 @@
+use crate::frontend::diagnostic::{Diagnostic, Stage};
+use crate::frontend::error::Error;
+use crate::frontend::expr::{Expr, LiteralValue, NodeId};
+use crate::frontend::fxunit::FxUnit;
+use crate::frontend::stmt::Stmt;
+use crate::frontend::tokens::{Token, TokenType};
+use crate::frontend::{expr, stmt};
+use crate::vm::chunk::{Constant, Function, Op, UpvalueRef};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+    //Captured locals are closed over instead of popped when their scope ends
+    captured: bool,
+}
+
+/*
+    What a return, break or continue has to undo on its way out, innermost last. Finally
+    blocks are compiled again at every early exit, the way they run in the tree-walker.
+*/
+#[derive(Clone)]
+enum Exit {
+    Loop { depth: usize, breaks: Vec<usize>, continues: Vec<usize> },
+    //A catch handler is installed
+    Handler,
+    //A finally handler is installed, leaving runs the block
+    Finally(Vec<Stmt>),
+    //Inside a finally block entered by an error, leaving drops the error
+    Pending,
+}
+
+struct FunctionState {
+    function: Function,
+    kind: FunctionKind,
+    locals: Vec<Local>,
+    scope_depth: usize,
+    exits: Vec<Exit>,
+    names: HashMap<String, u16>,
+    //Index into chunk.tokens of the token instructions are being emitted for
+    site: u32,
+}
+
+impl FunctionState {
+    fn new(name: &str, kind: FunctionKind, arity: usize) -> Self {
+        //Slot 0 holds the callee, or the instance in methods
+        let receiver = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            _ => ""
+        };
+        FunctionState {
+            function: Function { name: name.to_string(), arity, ..Function::default() },
+            kind,
+            locals: vec![Local { name: receiver.to_string(), depth: 0, captured: false }],
+            scope_depth: 0,
+            exits: Vec::new(),
+            names: HashMap::new(),
+            site: 0,
+        }
+    }
+}
+
+/*
+    Turns a resolved program into bytecode for the Vm. Locals live in stack slots and
+    closures capture them as upvalues, names the resolver left unresolved are globals
+    looked up by name, as they are in the tree-walker.
+*/
+pub struct Compiler {
+    states: Vec<FunctionState>,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Compiler::new()
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Compiler { states: Vec::new() }
+    }
+
+    //The script returns the value of a trailing expression statement, nil otherwise
+    pub fn compile(mut self, statements: &[Stmt]) -> Result<Rc<Function>, Error> {
+        self.states.push(FunctionState::new("<script>", FunctionKind::Script, 0));
+        self.mark(&Token::new(TokenType::Identifier, "<script>", 0));
+        let (last, rest) = match statements.split_last() {
+            Some((Stmt::Expression { expr, .. }, rest)) => (Some(expr), rest),
+            _ => (None, statements)
+        };
+        for statement in rest {
+            self.statement(statement)?;
+        }
+        match last {
+            Some(expr) => {
+                self.emit(Op::Tick);
+                self.expression(expr)?
+            },
+            None => self.emit(Op::Nil),
+        }
+        self.emit(Op::Return);
+        let state = self.states.pop().expect("Script state is missing.");
+        Ok(Rc::new(state.function))
+    }
+
+    fn state(&mut self) -> &mut FunctionState {
+        self.states.last_mut().expect("No function is being compiled.")
+    }
+
+    fn statement(&mut self, statement: &Stmt) -> Result<(), Error> {
+        self.emit(Op::Tick);
+        statement.accept(self)
+    }
+
+    fn expression(&mut self, expression: &Expr) -> Result<(), Error> {
+        expression.accept(self)
+    }
+
+    fn error(&self, token: &Token, message: &str) -> Error {
+        Error::Compile(vec![Diagnostic::at(Stage::Compile, token, message)])
+    }
+
+    fn current_token(&mut self) -> Token {
+        let state = self.state();
+        let site = state.site as usize;
+        state.function.chunk.tokens[site].clone()
+    }
+
+    //Instructions emitted from here on report errors at this token
+    fn mark(&mut self, token: &Token) {
+        let chunk = &mut self.state().function.chunk;
+        if chunk.tokens.last() != Some(token) {
+            chunk.tokens.push(token.clone());
+        }
+        let site = (chunk.tokens.len() - 1) as u32;
+        self.state().site = site;
+    }
+
+    fn emit(&mut self, op: Op) {
+        let state = self.state();
+        state.function.chunk.code.push(op);
+        state.function.chunk.sites.push(state.site);
+    }
+
+    fn offset(&self) -> usize {
+        self.states.last().map_or(0, |state| state.function.chunk.code.len())
+    }
+
+    //Emits a jump to be patched once its target is known
+    fn emit_jump(&mut self, op: fn(u16) -> Op) -> usize {
+        self.emit(op(u16::MAX));
+        self.offset() - 1
+    }
+
+    fn patch_jump(&mut self, at: usize) -> Result<(), Error> {
+        let distance = self.offset() - at - 1;
+        let distance = self.operand(distance, "Too much code to jump over.")?;
+        let code = &mut self.state().function.chunk.code;
+        code[at] = match code[at] {
+            Op::Jump(_) => Op::Jump(distance),
+            Op::JumpIfFalse(_) => Op::JumpIfFalse(distance),
+            Op::PushCatch(_) => Op::PushCatch(distance),
+            Op::PushFinally(_) => Op::PushFinally(distance),
+            other => unreachable!("{:?} is not a jump", other)
+        };
+        Ok(())
+    }
+
+    fn emit_loop(&mut self, start: usize) -> Result<(), Error> {
+        let distance = self.operand(self.offset() + 1 - start, "Loop body too large.")?;
+        self.emit(Op::Loop(distance));
+        Ok(())
+    }
+
+    fn operand(&mut self, n: usize, message: &str) -> Result<u16, Error> {
+        if n > u16::MAX as usize {
+            let token = self.current_token();
+            return Err(self.error(&token, message));
+        }
+        Ok(n as u16)
+    }
+
+    fn constant(&mut self, constant: Constant) -> Result<u16, Error> {
+        let index = self.state().function.chunk.constants.len();
+        let index = self.operand(index, "Too many constants in one function.")?;
+        self.state().function.chunk.constants.push(constant);
+        Ok(index)
+    }
+
+    fn name(&mut self, name: &str) -> Result<u16, Error> {
+        if let Some(&index) = self.state().names.get(name) {
+            return Ok(index);
+        }
+        let index = self.constant(Constant::Name(name.to_string()))?;
+        self.state().names.insert(name.to_string(), index);
+        Ok(index)
+    }
+
+    fn begin_scope(&mut self) {
+        self.state().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let state = self.state();
+        state.scope_depth -= 1;
+        let depth = state.scope_depth;
+        while let Some(local) = self.state().locals.last() {
+            if local.depth <= depth {
+                break;
+            }
+            let op = if local.captured { Op::CloseUpvalue } else { Op::Pop };
+            self.state().locals.pop();
+            self.emit(op);
+        }
+    }
+
+    //Pops locals deeper than `depth` off the stack but keeps compiling with them in scope
+    fn discard_locals(&mut self, depth: usize) {
+        let ops: Vec<Op> = self.state().locals.iter().rev()
+            .take_while(|local| local.depth > depth)
+            .map(|local| if local.captured { Op::CloseUpvalue } else { Op::Pop })
+            .collect();
+        for op in ops {
+            self.emit(op);
+        }
+    }
+
+    fn add_local(&mut self, name: &str) -> Result<(), Error> {
+        if self.state().locals.len() > u16::MAX as usize {
+            let token = self.current_token();
+            return Err(self.error(&token, "Too many local variables in one function."));
+        }
+        let state = self.state();
+        let depth = state.scope_depth;
+        state.locals.push(Local { name: name.to_string(), depth, captured: false });
+        Ok(())
+    }
+
+    //Binds the value on top of the stack to the name, in the current scope or as a global
+    fn define_variable(&mut self, name: &Token) -> Result<(), Error> {
+        if self.state().scope_depth > 0 {
+            return self.add_local(&name.lexeme);
+        }
+        self.mark(name);
+        let index = self.name(&name.lexeme)?;
+        self.emit(Op::DefineGlobal(index));
+        Ok(())
+    }
+
+    fn resolve_local(&self, level: usize, name: &str) -> Option<u16> {
+        self.states[level].locals.iter().rposition(|local| local.name == name).map(|slot| slot as u16)
+    }
+
+    fn resolve_upvalue(&mut self, level: usize, name: &str) -> Result<Option<u16>, Error> {
+        if level == 0 {
+            return Ok(None);
+        }
+        if let Some(slot) = self.resolve_local(level - 1, name) {
+            self.states[level - 1].locals[slot as usize].captured = true;
+            return self.add_upvalue(level, UpvalueRef { is_local: true, index: slot }).map(Some);
+        }
+        match self.resolve_upvalue(level - 1, name)? {
+            Some(index) => self.add_upvalue(level, UpvalueRef { is_local: false, index }).map(Some),
+            None => Ok(None)
+        }
+    }
+
+    fn add_upvalue(&mut self, level: usize, upvalue: UpvalueRef) -> Result<u16, Error> {
+        let upvalues = &mut self.states[level].function.upvalues;
+        if let Some(index) = upvalues.iter().position(|existing| *existing == upvalue) {
+            return Ok(index as u16);
+        }
+        upvalues.push(upvalue);
+        let count = upvalues.len() - 1;
+        self.operand(count, "Too many closure variables in one function.")
+    }
+
+    fn get_variable(&mut self, name: &str) -> Result<(), Error> {
+        let level = self.states.len() - 1;
+        let op = if let Some(slot) = self.resolve_local(level, name) {
+            Op::GetLocal(slot)
+        } else if let Some(index) = self.resolve_upvalue(level, name)? {
+            Op::GetUpvalue(index)
+        } else {
+            Op::GetGlobal(self.name(name)?)
+        };
+        self.emit(op);
+        Ok(())
+    }
+
+    fn set_variable(&mut self, name: &str) -> Result<(), Error> {
+        let level = self.states.len() - 1;
+        let op = if let Some(slot) = self.resolve_local(level, name) {
+            Op::SetLocal(slot)
+        } else if let Some(index) = self.resolve_upvalue(level, name)? {
+            Op::SetUpvalue(index)
+        } else {
+            Op::SetGlobal(self.name(name)?)
+        };
+        self.emit(op);
+        Ok(())
+    }
+
+    fn block(&mut self, statements: &[Stmt]) -> Result<(), Error> {
+        self.begin_scope();
+        for statement in statements {
+            self.statement(statement)?;
+        }
+        self.end_scope();
+        Ok(())
+    }
+
+    //Compiles a function body and emits the closure for it
+    fn function(&mut self, name: &Token, params: &[Token], body: &[Stmt], kind: FunctionKind) -> Result<(), Error> {
+        self.states.push(FunctionState::new(&name.lexeme, kind, params.len()));
+        self.mark(name);
+        self.begin_scope();
+        for param in params {
+            self.add_local(&param.lexeme)?;
+        }
+        for statement in body {
+            self.statement(statement)?;
+        }
+        self.implicit_return();
+        let state = self.states.pop().expect("Function state is missing.");
+        self.mark(name);
+        let index = self.constant(Constant::Function(Rc::new(state.function)))?;
+        self.emit(Op::Closure(index));
+        Ok(())
+    }
+
+    fn implicit_return(&mut self) {
+        if self.state().kind == FunctionKind::Initializer {
+            self.emit(Op::GetLocal(0));
+        } else {
+            self.emit(Op::Nil);
+        }
+        self.emit(Op::Return);
+    }
+
+    /*
+        Emits what leaving through the enclosing exits takes: popping handlers and running
+        finally blocks, up to the innermost loop for break and continue or out of the
+        function for return. Gives back the index of that loop. `carried` is a value on
+        the stack above the locals, the return value, that finally blocks must not clobber.
+    */
+    fn leave(&mut self, to_loop: bool, carried: bool) -> Result<Option<usize>, Error> {
+        let mut i = self.state().exits.len();
+        while i > 0 {
+            i -= 1;
+            match self.state().exits[i].clone() {
+                Exit::Loop { .. } if to_loop => return Ok(Some(i)),
+                Exit::Loop { .. } => (),
+                Exit::Handler => self.emit(Op::PopHandler),
+                Exit::Pending => self.emit(Op::DropPending),
+                Exit::Finally(finally) => {
+                    self.emit(Op::PopHandler);
+                    //The block runs as if written outside its try, so its own exits skip it
+                    let inner = self.state().exits.split_off(i);
+                    if carried {
+                        self.add_local("")?;
+                    }
+                    self.block(&finally)?;
+                    if carried {
+                        self.state().locals.pop();
+                    }
+                    self.state().exits.extend(inner);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn loop_exit(&mut self, keyword: &Token, is_break: bool) -> Result<(), Error> {
+        self.mark(keyword);
+        let index = match self.leave(true, false)? {
+            Some(index) => index,
+            None => return Err(self.error(keyword, "Cannot leave a loop from outside of one."))
+        };
+        let depth = match self.state().exits[index] {
+            Exit::Loop { depth, .. } => depth,
+            _ => unreachable!()
+        };
+        self.discard_locals(depth);
+        let jump = self.emit_jump(Op::Jump);
+        if let Exit::Loop { ref mut breaks, ref mut continues, .. } = self.state().exits[index] {
+            if is_break { breaks.push(jump) } else { continues.push(jump) }
+        }
+        Ok(())
+    }
+}
+
+impl stmt::Visitor<()> for Compiler {
+    fn visit_block_stmt(&mut self, statements: &[Stmt]) -> Result<(), Error> {
+        self.block(statements)
+    }
+
+    fn visit_import_stmt(&mut self, keyword: &Token, path: &str, name: &Token) -> Result<(), Error> {
+        self.mark(keyword);
+        let index = self.name(path)?;
+        self.emit(Op::Import(index));
+        self.define_variable(name)
+    }
+
+    fn visit_export_stmt(&mut self, keyword: &Token, declaration: &Stmt) -> Result<(), Error> {
+        self.statement(declaration)?;
+        let name = match declaration {
+            Stmt::Var { name, .. } | Stmt::FxFx { name, .. } | Stmt::Class { name, .. } => name,
+            _ => unreachable!()
+        };
+        self.mark(keyword);
+        let index = self.name(&name.lexeme)?;
+        self.emit(Op::Export(index));
+        Ok(())
+    }
+
+    fn visit_func_stmt(&mut self, name: &Token, params: &[Token], body: &[Stmt]) -> Result<(), Error> {
+        //Declared first so the body can call itself
+        if self.state().scope_depth > 0 {
+            self.add_local(&name.lexeme)?;
+            return self.function(name, params, body, FunctionKind::Function);
+        }
+        self.function(name, params, body, FunctionKind::Function)?;
+        self.define_variable(name)
+    }
+
+    fn visit_class_stmt(&mut self, name: &Token, superclass: &Option<Expr>, methods: &[Stmt]) -> Result<(), Error> {
+        self.mark(name);
+        let index = self.name(&name.lexeme)?;
+        let local = self.state().scope_depth > 0;
+        if local {
+            self.add_local(&name.lexeme)?;
+        }
+        self.emit(Op::Class(index));
+        if !local {
+            self.emit(Op::DefineGlobal(index));
+        }
+
+        //Methods reach the superclass through a `super` local wrapped around them
+        if let Some(Expr::Variable { name: superclass, .. }) = superclass {
+            self.mark(superclass);
+            self.get_variable(&superclass.lexeme)?;
+            self.begin_scope();
+            self.add_local("super")?;
+            self.get_variable(&name.lexeme)?;
+            self.emit(Op::Inherit);
+        }
+
+        self.mark(name);
+        self.get_variable(&name.lexeme)?;
+        for method in methods {
+            if let Stmt::FxFx { name, params, body, .. } = method {
+                let kind = if name.lexeme == "init" { FunctionKind::Initializer } else { FunctionKind::Method };
+                self.function(name, params, body, kind)?;
+                let index = self.name(&name.lexeme)?;
+                self.emit(Op::Method(index));
+            } else {
+                unreachable!()
+            }
+        }
+        self.emit(Op::Pop);
+        if superclass.is_some() {
+            self.end_scope();
+        }
+        Ok(())
+    }
+
+    fn visit_return_stmt(&mut self, keyword: &Token, value: &Option<Expr>) -> Result<(), Error> {
+        match value {
+            Some(value) => self.expression(value)?,
+            None => self.emit(Op::Nil),
+        }
+        //Initializers give back the instance whatever they return
+        if self.state().kind == FunctionKind::Initializer {
+            self.emit(Op::Pop);
+            self.emit(Op::GetLocal(0));
+        }
+        self.mark(keyword);
+        self.leave(false, true)?;
+        self.emit(Op::Return);
+        Ok(())
+    }
+
+    fn visit_if_stmt(&mut self, condition: &Expr, else_branch: &Option<Stmt>, then_branch: &Stmt) -> Result<(), Error> {
+        self.expression(condition)?;
+        let to_else = self.emit_jump(Op::JumpIfFalse);
+        self.emit(Op::Pop);
+        self.statement(then_branch)?;
+        let to_end = self.emit_jump(Op::Jump);
+        self.patch_jump(to_else)?;
+        self.emit(Op::Pop);
+        if let Some(else_branch) = else_branch {
+            self.statement(else_branch)?;
+        }
+        self.patch_jump(to_end)
+    }
+
+    fn visit_while_stmt(&mut self, condition: &Expr, statement: &Stmt, increment: &Option<Expr>) -> Result<(), Error> {
+        let start = self.offset();
+        self.expression(condition)?;
+        let to_exit = self.emit_jump(Op::JumpIfFalse);
+        self.emit(Op::Pop);
+
+        let depth = self.state().scope_depth;
+        self.state().exits.push(Exit::Loop { depth, breaks: Vec::new(), continues: Vec::new() });
+        self.statement(statement)?;
+        let (breaks, continues) = match self.state().exits.pop() {
+            Some(Exit::Loop { breaks, continues, .. }) => (breaks, continues),
+            _ => unreachable!()
+        };
+
+        for jump in continues {
+            self.patch_jump(jump)?;
+        }
+        if let Some(increment) = increment {
+            self.expression(increment)?;
+            self.emit(Op::Pop);
+        }
+        self.emit_loop(start)?;
+        self.patch_jump(to_exit)?;
+        self.emit(Op::Pop);
+        for jump in breaks {
+            self.patch_jump(jump)?;
+        }
+        Ok(())
+    }
+
+    fn visit_break_stmt(&mut self, keyword: &Token) -> Result<(), Error> {
+        self.loop_exit(keyword, true)
+    }
+
+    fn visit_continue_stmt(&mut self, keyword: &Token) -> Result<(), Error> {
+        self.loop_exit(keyword, false)
+    }
+
+    fn visit_throw_stmt(&mut self, keyword: &Token, value: &Expr) -> Result<(), Error> {
+        self.expression(value)?;
+        self.mark(keyword);
+        self.emit(Op::Throw);
+        Ok(())
+    }
+
+    /*
+        try { body } catch (e) { handler } finally { cleanup } becomes
+
+                PushFinally error_path
+                PushCatch catch
+                body
+                PopHandler
+                Jump done
+        catch:  handler, with e bound to the caught value
+        done:   PopHandler
+                cleanup
+                Jump end
+        error_path:
+                cleanup
+                Rethrow
+        end:
+    */
+    fn visit_try_stmt(&mut self, body: &[Stmt], catch: &Option<(Token, Vec<Stmt>)>, finally: &Option<Vec<Stmt>>) -> Result<(), Error> {
+        let error_path = match finally {
+            Some(finally) => {
+                let jump = self.emit_jump(Op::PushFinally);
+                self.state().exits.push(Exit::Finally(finally.clone()));
+                Some(jump)
+            },
+            None => None
+        };
+
+        match catch {
+            Some((name, handler)) => {
+                let to_catch = self.emit_jump(Op::PushCatch);
+                self.state().exits.push(Exit::Handler);
+                self.block(body)?;
+                self.state().exits.pop();
+                self.emit(Op::PopHandler);
+                let done = self.emit_jump(Op::Jump);
+                self.patch_jump(to_catch)?;
+                self.begin_scope();
+                self.add_local(&name.lexeme)?;
+                for statement in handler {
+                    self.statement(statement)?;
+                }
+                self.end_scope();
+                self.patch_jump(done)?;
+            },
+            None => self.block(body)?
+        }
+
+        if let (Some(error_path), Some(finally)) = (error_path, finally) {
+            self.state().exits.pop();
+            self.emit(Op::PopHandler);
+            self.block(finally)?;
+            let end = self.emit_jump(Op::Jump);
+            self.patch_jump(error_path)?;
+            self.state().exits.push(Exit::Pending);
+            self.block(finally)?;
+            self.state().exits.pop();
+            self.emit(Op::Rethrow);
+            self.patch_jump(end)?;
+        }
+        Ok(())
+    }
+
+    fn visit_expression_stmt(&mut self, expr: &Expr) -> Result<(), Error> {
+        self.expression(expr)?;
+        self.emit(Op::Pop);
+        Ok(())
+    }
+
+    fn visit_var_stmt(&mut self, name: &Token, initializer: &Option<Expr>) -> Result<(), Error> {
+        //A local is in scope for its own initializer, as in the resolver, so closures there can
+        //capture it. Its slot is where the initializer's value lands
+        let local = self.state().scope_depth > 0;
+        if local {
+            self.add_local(&name.lexeme)?;
+        }
+        match initializer {
+            Some(initializer) => self.expression(initializer)?,
+            None => self.emit(Op::Nil),
+        }
+        if local {
+            return Ok(());
+        }
+        self.define_variable(name)
+    }
+
+    fn visit_print_stmt(&mut self, expr: &Expr) -> Result<(), Error> {
+        self.expression(expr)?;
+        self.emit(Op::Print);
+        Ok(())
+    }
+}
+
+impl expr::Visitor<()> for Compiler {
+    fn visit_assign_expr(&mut self, _id: NodeId, name: &Token, val: &Expr) -> Result<(), Error> {
+        self.expression(val)?;
+        self.mark(name);
+        self.set_variable(&name.lexeme)
+    }
+
+    fn visit_binary_expr(&mut self, lhs: &Expr, rhs: &Expr, op: &Token) -> Result<(), Error> {
+        self.expression(lhs)?;
+        self.expression(rhs)?;
+        self.mark(op);
+        let op = match op.token_type {
+            TokenType::Minus => Op::Subtract,
+            TokenType::Slash => Op::Divide,
+            TokenType::Star => Op::Multiply,
+            TokenType::Plus => Op::Add,
+            TokenType::Greater => Op::Greater,
+            TokenType::GreaterEqual => Op::GreaterEqual,
+            TokenType::Less => Op::Less,
+            TokenType::LessEqual => Op::LessEqual,
+            TokenType::BangEqual => Op::NotEqual,
+            TokenType::EqualEqual => Op::Equal,
+            _ => unreachable!()
+        };
+        self.emit(op);
+        Ok(())
+    }
+
+    fn visit_call_expr(&mut self, callee: &Expr, paren: &Token, arguments: &[Expr]) -> Result<(), Error> {
+        self.expression(callee)?;
+        for argument in arguments {
+            self.expression(argument)?;
+        }
+        self.mark(paren);
+        if arguments.len() > u8::MAX as usize {
+            return Err(self.error(paren, "Cannot have more than 255 arguements."));
+        }
+        self.emit(Op::Call(arguments.len() as u8));
+        Ok(())
+    }
+
+    fn visit_get_expr(&mut self, object: &Expr, name: &Token) -> Result<(), Error> {
+        self.expression(object)?;
+        self.mark(name);
+        let index = self.name(&name.lexeme)?;
+        self.emit(Op::GetProperty(index));
+        Ok(())
+    }
+
+    fn visit_set_expr(&mut self, object: &Expr, name: &Token, value: &Expr) -> Result<(), Error> {
+        self.expression(object)?;
+        self.expression(value)?;
+        self.mark(name);
+        let index = self.name(&name.lexeme)?;
+        self.emit(Op::SetProperty(index));
+        Ok(())
+    }
+
+    fn visit_super_expr(&mut self, _id: NodeId, keyword: &Token, method: &Token) -> Result<(), Error> {
+        self.mark(keyword);
+        self.get_variable("this")?;
+        self.get_variable("super")?;
+        self.mark(method);
+        let index = self.name(&method.lexeme)?;
+        self.emit(Op::GetSuper(index));
+        Ok(())
+    }
+
+    fn visit_this_expr(&mut self, _id: NodeId, keyword: &Token) -> Result<(), Error> {
+        self.mark(keyword);
+        self.get_variable("this")
+    }
+
+    fn visit_grouping_expr(&mut self, expr: &Expr) -> Result<(), Error> {
+        self.expression(expr)
+    }
+
+    fn visit_lambda_expr(&mut self, keyword: &Token, params: &[Token], body: &[Stmt]) -> Result<(), Error> {
+        let name = Token::spanned(TokenType::Identifier, "lambda", keyword.line, keyword.span);
+        self.function(&name, params, body, FunctionKind::Function)
+    }
+
+    fn visit_list_expr(&mut self, bracket: &Token, elements: &[Expr]) -> Result<(), Error> {
+        for element in elements {
+            self.expression(element)?;
+        }
+        self.mark(bracket);
+        let count = self.operand(elements.len(), "Too many elements in a list literal.")?;
+        self.emit(Op::List(count));
+        Ok(())
+    }
+
+    fn visit_map_expr(&mut self, brace: &Token, entries: &[(Expr, Expr)]) -> Result<(), Error> {
+        for (key, value) in entries {
+            self.expression(key)?;
+            self.expression(value)?;
+        }
+        self.mark(brace);
+        let count = self.operand(entries.len(), "Too many entries in a map literal.")?;
+        self.emit(Op::Map(count));
+        Ok(())
+    }
+
+    fn visit_index_expr(&mut self, object: &Expr, bracket: &Token, index: &Expr) -> Result<(), Error> {
+        self.expression(object)?;
+        self.expression(index)?;
+        self.mark(bracket);
+        self.emit(Op::GetIndex);
+        Ok(())
+    }
+
+    fn visit_set_index_expr(&mut self, object: &Expr, bracket: &Token, index: &Expr, value: &Expr) -> Result<(), Error> {
+        self.expression(object)?;
+        self.expression(index)?;
+        self.expression(value)?;
+        self.mark(bracket);
+        self.emit(Op::SetIndex);
+        Ok(())
+    }
+
+    fn visit_literal_expr(&mut self, val: &LiteralValue) -> Result<(), Error> {
+        let op = match val {
+            LiteralValue::Nil => Op::Nil,
+            LiteralValue::Boolean(true) => Op::True,
+            LiteralValue::Boolean(false) => Op::False,
+            LiteralValue::Number(n) => Op::Constant(self.constant(Constant::Value(FxUnit::Number(*n)))?),
+            LiteralValue::String(s) => Op::Constant(self.constant(Constant::Value(FxUnit::String(s.clone())))?),
+        };
+        self.emit(op);
+        Ok(())
+    }
+
+    fn visit_logical_expr(&mut self, lhs: &Expr, rhs: &Expr, op: &Token) -> Result<(), Error> {
+        self.expression(lhs)?;
+        if op.token_type == TokenType::Or {
+            let to_rhs = self.emit_jump(Op::JumpIfFalse);
+            let to_end = self.emit_jump(Op::Jump);
+            self.patch_jump(to_rhs)?;
+            self.emit(Op::Pop);
+            self.expression(rhs)?;
+            self.patch_jump(to_end)
+        } else {
+            let to_end = self.emit_jump(Op::JumpIfFalse);
+            self.emit(Op::Pop);
+            self.expression(rhs)?;
+            self.patch_jump(to_end)
+        }
+    }
+
+    fn visit_unary_expr(&mut self, op: &Token, rhs: &Expr) -> Result<(), Error> {
+        self.expression(rhs)?;
+        self.mark(op);
+        match op.token_type {
+            TokenType::Minus => self.emit(Op::Negate),
+            TokenType::Bang => self.emit(Op::Not),
+            _ => unreachable!()
+        }
+        Ok(())
+    }
+
+    fn visit_variable_expr(&mut self, _id: NodeId, name: &Token) -> Result<(), Error> {
+        self.mark(name);
+        self.get_variable(&name.lexeme)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::lexer::Lexer;
+    use crate::frontend::parser::Parser;
+    use std::mem;
+
+    fn compile(src: &str) -> Rc<Function> {
+        let tokens = Lexer::new(src.to_string()).scan_tokens().unwrap();
+        let statements = Parser::new(tokens).parse().unwrap();
+        Compiler::new().compile(&statements).unwrap()
+    }
+
+    #[test]
+    fn ops_stay_compact() {
+        assert_eq!(mem::size_of::<Op>(), 4);
+    }
+
+    #[test]
+    fn locals_use_slots_and_closures_capture_them() {
+        let script = compile("{ var a = 1; fn get() -> { return a; } }");
+        let code = &script.chunk.code;
+        assert!(code.contains(&Op::Closure(1)));
+        assert!(code.contains(&Op::CloseUpvalue));
+        let get = match &script.chunk.constants[1] {
+            Constant::Function(get) => Rc::clone(get),
+            other => panic!("unexpected {:?}", other)
+        };
+        assert_eq!(get.upvalues, vec![UpvalueRef { is_local: true, index: 1 }]);
+        assert_eq!(get.chunk.code, vec![Op::Tick, Op::GetUpvalue(0), Op::Return, Op::Nil, Op::Return]);
+    }
+}
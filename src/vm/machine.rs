@@ -0,0 +1,521 @@
+use crate::frontend::env::Environment;
+use crate::frontend::error::Error;
+use crate::frontend::fxclass::{FxClass, FxClassInstance};
+use crate::frontend::fxfx::{Arity, FxFx};
+use crate::frontend::fxunit::FxUnit;
+use crate::frontend::interpreter::Interpreter;
+use crate::frontend::stmt::Stmt;
+use crate::frontend::tokens::Token;
+use crate::frontend::trace::{Frame, Traceback};
+use crate::vm::chunk::{Constant, Function, Op};
+use crate::vm::compiler::Compiler;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+//A function together with the variables it captured and the globals of the file it came from
+pub struct Closure {
+    pub function: Rc<Function>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    pub globals: Rc<RefCell<Environment>>,
+}
+
+//A captured variable, on the stack while its scope is live and moved into the upvalue after
+pub enum Upvalue {
+    Open(usize),
+    Closed(FxUnit),
+}
+
+/*
+    Values and open upvalues shared by every Vm running on an interpreter. A native like
+    map() that calls back into a closure starts a nested Vm above the caller's values, so
+    upvalues still open in the caller point at the right slots.
+*/
+#[derive(Default)]
+pub struct Stack {
+    values: Vec<FxUnit>,
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    //Stack index of slot 0
+    base: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum HandlerKind {
+    Catch,
+    Finally,
+}
+
+//An installed catch or finally, with what to unwind to when an error reaches it
+struct Handler {
+    kind: HandlerKind,
+    target: usize,
+    frames: usize,
+    stack: usize,
+    call_stack: usize,
+}
+
+//Compiles resolved statements and runs them as a script against the interpreter's globals
+pub fn run(interpreter: &mut Interpreter, statements: &[Stmt]) -> Result<FxUnit, Error> {
+    let function = Compiler::new().compile(statements)?;
+    let closure = Rc::new(Closure { function, upvalues: Vec::new(), globals: Rc::clone(&interpreter.globals) });
+    call(interpreter, &closure, None, &[])
+}
+
+//Runs an imported file, see Interpreter::import_module
+pub fn run_module(interpreter: &mut Interpreter, statements: &[Stmt]) -> Result<(), Error> {
+    run(interpreter, statements).map(|_| ())
+}
+
+//Calls a closure from Rust, the way natives and the host reach compiled code
+pub fn call(interpreter: &mut Interpreter, closure: &Rc<Closure>, this: Option<&FxUnit>, args: &[FxUnit]) -> Result<FxUnit, Error> {
+    let mut vm = Vm::new(interpreter);
+    let base = vm.interpreter.stack.values.len();
+    vm.push(this.cloned().unwrap_or(FxUnit::Nil));
+    vm.interpreter.stack.values.extend_from_slice(args);
+    vm.frames.push(CallFrame { closure: Rc::clone(closure), ip: 0, base });
+    vm.execute()
+}
+
+/*
+    Runs bytecode on the interpreter's stack. Each Vm lives for one call from Rust: fx
+    calls inside it push frames rather than recursing, and the interpreter's call stack,
+    traceback and limits behave as they do for the tree-walker.
+*/
+struct Vm<'a> {
+    interpreter: &'a mut Interpreter,
+    frames: Vec<CallFrame>,
+    handlers: Vec<Handler>,
+    //Errors waiting for their finally block to end, with the traceback they had
+    pending: Vec<(Error, Option<Traceback>)>,
+    call_depth: usize,
+}
+
+impl<'a> Vm<'a> {
+    fn new(interpreter: &'a mut Interpreter) -> Self {
+        let call_depth = interpreter.call_stack().len();
+        Vm { interpreter, frames: Vec::new(), handlers: Vec::new(), pending: Vec::new(), call_depth }
+    }
+
+    fn push(&mut self, value: FxUnit) {
+        self.interpreter.stack.values.push(value);
+    }
+
+    fn pop(&mut self) -> FxUnit {
+        self.interpreter.stack.values.pop().expect("Stack underflow.")
+    }
+
+    fn peek(&self, distance: usize) -> &FxUnit {
+        let values = &self.interpreter.stack.values;
+        &values[values.len() - 1 - distance]
+    }
+
+    fn frame(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("No frame is running.")
+    }
+
+    fn execute(&mut self) -> Result<FxUnit, Error> {
+        loop {
+            match self.step() {
+                Ok(Some(value)) => return Ok(value),
+                Ok(None) => (),
+                Err(error) => {
+                    self.interpreter.note_traceback(&error, None);
+                    self.unwind(error)?;
+                }
+            }
+        }
+    }
+
+    //Hands the error to the innermost handler, or cleans up this run when there is none
+    fn unwind(&mut self, error: Error) -> Result<(), Error> {
+        while let Some(handler) = self.handlers.pop() {
+            let caught = match handler.kind {
+                HandlerKind::Catch => match self.interpreter.caught_value(&error) {
+                    Some(value) => Some(value),
+                    None => continue
+                },
+                HandlerKind::Finally => None
+            };
+            self.frames.truncate(handler.frames);
+            self.close_upvalues(handler.stack);
+            self.interpreter.stack.values.truncate(handler.stack);
+            self.interpreter.truncate_call_stack(handler.call_stack);
+            match caught {
+                Some(value) => {
+                    self.interpreter.clear_traceback();
+                    self.push(value);
+                },
+                None => {
+                    let traceback = self.interpreter.take_traceback();
+                    self.pending.push((error, traceback));
+                }
+            }
+            self.frame().ip = handler.target;
+            return Ok(());
+        }
+        let base = self.frames.first().map_or(self.interpreter.stack.values.len(), |frame| frame.base);
+        self.close_upvalues(base);
+        self.interpreter.stack.values.truncate(base);
+        self.interpreter.truncate_call_stack(self.call_depth);
+        self.frames.clear();
+        Err(error)
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let open = &mut self.interpreter.stack.open_upvalues;
+        let existing = open.iter().find(|upvalue| matches!(*upvalue.borrow(), Upvalue::Open(s) if s == slot));
+        if let Some(upvalue) = existing {
+            return Rc::clone(upvalue);
+        }
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        open.push(Rc::clone(&upvalue));
+        upvalue
+    }
+
+    //Moves variables at or above `from` off the stack into the closures that captured them
+    fn close_upvalues(&mut self, from: usize) {
+        let stack = &mut self.interpreter.stack;
+        let values = &stack.values;
+        stack.open_upvalues.retain(|upvalue| {
+            let slot = match *upvalue.borrow() {
+                Upvalue::Open(slot) => slot,
+                Upvalue::Closed(_) => return false
+            };
+            if slot < from {
+                return true;
+            }
+            *upvalue.borrow_mut() = Upvalue::Closed(values[slot].clone());
+            false
+        });
+    }
+
+    fn step(&mut self) -> Result<Option<FxUnit>, Error> {
+        let frame = self.frame();
+        let closure = Rc::clone(&frame.closure);
+        let ip = frame.ip;
+        let base = frame.base;
+        frame.ip += 1;
+        let chunk = &closure.function.chunk;
+        let token = || chunk.token_at(ip);
+        let runtime = |message: &str| Error::Runtime { token: token().clone(), message: message.to_string() };
+
+        match chunk.code[ip] {
+            Op::Constant(index) => match chunk.constants[index as usize] {
+                Constant::Value(ref value) => self.push(value.clone()),
+                ref other => unreachable!("{:?} is not a value", other)
+            },
+            Op::Nil => self.push(FxUnit::Nil),
+            Op::True => self.push(FxUnit::Boolean(true)),
+            Op::False => self.push(FxUnit::Boolean(false)),
+            Op::Pop => {
+                self.pop();
+            },
+            Op::GetLocal(slot) => {
+                let value = self.interpreter.stack.values[base + slot as usize].clone();
+                self.push(value);
+            },
+            Op::SetLocal(slot) => {
+                let value = self.peek(0).clone();
+                self.interpreter.stack.values[base + slot as usize] = value;
+            },
+            Op::GetGlobal(index) => {
+                let name = chunk.name(index);
+                let value = closure.globals.borrow().lookup(name);
+                match value {
+                    Some(value) => self.push(value),
+                    None => return Err(runtime(&format!("Undefined variable '{}'.", name)))
+                }
+            },
+            Op::DefineGlobal(index) => {
+                let value = self.pop();
+                closure.globals.borrow_mut().define(chunk.name(index).to_string(), value);
+            },
+            Op::SetGlobal(index) => {
+                let name = chunk.name(index);
+                let value = self.peek(0).clone();
+                if !closure.globals.borrow_mut().set(name, value) {
+                    return Err(runtime(&format!("Undefined variable '{}'.", name)));
+                }
+            },
+            Op::GetUpvalue(index) => {
+                let value = match *closure.upvalues[index as usize].borrow() {
+                    Upvalue::Open(slot) => self.interpreter.stack.values[slot].clone(),
+                    Upvalue::Closed(ref value) => value.clone()
+                };
+                self.push(value);
+            },
+            Op::SetUpvalue(index) => {
+                let value = self.peek(0).clone();
+                match *closure.upvalues[index as usize].borrow_mut() {
+                    Upvalue::Open(slot) => self.interpreter.stack.values[slot] = value,
+                    Upvalue::Closed(ref mut closed) => *closed = value
+                }
+            },
+            Op::GetProperty(_) => {
+                let object = self.pop();
+                let value = match object {
+                    FxUnit::Instance(ref instance) => instance.borrow().get(token(), &object)?,
+                    FxUnit::Module(ref module) => module.get(token())?,
+                    _ => return Err(runtime("Only instances can have props."))
+                };
+                self.push(value);
+            },
+            Op::SetProperty(_) => {
+                let value = self.pop();
+                let object = self.pop();
+                match object {
+                    FxUnit::Instance(ref instance) => instance.borrow_mut().set(token(), value),
+                    _ => return Err(runtime("Only instances have fields."))
+                }
+                self.push(object);
+            },
+            Op::GetSuper(index) => {
+                let superclass = self.pop();
+                let instance = self.pop();
+                let name = chunk.name(index);
+                let method = match superclass {
+                    FxUnit::Class(ref superclass) => superclass.borrow().find_method(name),
+                    _ => unreachable!()
+                };
+                match method {
+                    Some(method) => self.push(FxUnit::Callable(method.bind(instance))),
+                    None => return Err(runtime(&format!("Undefined prop:{}", name)))
+                }
+            },
+            Op::GetIndex => {
+                let index = self.pop();
+                let object = self.pop();
+                let value = self.interpreter.index(token(), &object, &index)?;
+                self.push(value);
+            },
+            Op::SetIndex => {
+                let value = self.pop();
+                let index = self.pop();
+                let object = self.pop();
+                let value = self.interpreter.set_index(token(), &object, &index, value)?;
+                self.push(value);
+            },
+            Op::Equal | Op::NotEqual => {
+                let right = self.pop();
+                let left = self.pop();
+                let equal = left.equals(&right);
+                self.push(FxUnit::Boolean(equal == (chunk.code[ip] == Op::Equal)));
+            },
+            op @ (Op::Greater | Op::GreaterEqual | Op::Less | Op::LessEqual | Op::Add | Op::Subtract | Op::Multiply | Op::Divide) => {
+                let right = self.pop();
+                let left = self.pop();
+                let value = match (op, left, right) {
+                    (op, FxUnit::Number(l), FxUnit::Number(r)) => match op {
+                        Op::Greater => FxUnit::Boolean(l > r),
+                        Op::GreaterEqual => FxUnit::Boolean(l >= r),
+                        Op::Less => FxUnit::Boolean(l < r),
+                        Op::LessEqual => FxUnit::Boolean(l <= r),
+                        Op::Add => FxUnit::Number(l + r),
+                        Op::Subtract => FxUnit::Number(l - r),
+                        Op::Multiply => FxUnit::Number(l * r),
+                        _ => FxUnit::Number(l / r),
+                    },
+                    (Op::Add, FxUnit::String(l), FxUnit::String(r)) => FxUnit::String(l + &r),
+                    (Op::Add, _, _) => return Err(runtime("Operands must be numbers or strings")),
+                    _ => return Err(runtime("Operand must be a number"))
+                };
+                self.push(value);
+            },
+            Op::Not => {
+                let value = self.pop();
+                self.push(FxUnit::Boolean(!is_truthy(&value)));
+            },
+            Op::Negate => match self.pop() {
+                FxUnit::Number(n) => self.push(FxUnit::Number(-n)),
+                _ => return Err(runtime("Operand must be a number"))
+            },
+            Op::Print => {
+                let value = self.pop();
+                writeln!(self.interpreter.output(), "{}", value)?;
+            },
+            Op::Jump(offset) => self.frame().ip += offset as usize,
+            Op::JumpIfFalse(offset) => {
+                if !is_truthy(self.peek(0)) {
+                    self.frame().ip += offset as usize;
+                }
+            },
+            Op::Loop(offset) => self.frame().ip -= offset as usize,
+            Op::Call(count) => self.call_value(count as usize, token())?,
+            Op::Closure(index) => {
+                let function = match chunk.constants[index as usize] {
+                    Constant::Function(ref function) => Rc::clone(function),
+                    ref other => unreachable!("{:?} is not a function", other)
+                };
+                let upvalues = function.upvalues.iter().map(|upvalue| {
+                    if upvalue.is_local {
+                        self.capture_upvalue(base + upvalue.index as usize)
+                    } else {
+                        Rc::clone(&closure.upvalues[upvalue.index as usize])
+                    }
+                }).collect();
+                let closure = Closure { function, upvalues, globals: Rc::clone(&closure.globals) };
+                self.push(FxUnit::Callable(FxFx::Compiled { closure: Rc::new(closure), this: None }));
+            },
+            Op::CloseUpvalue => {
+                let top = self.interpreter.stack.values.len() - 1;
+                self.close_upvalues(top);
+                self.pop();
+            },
+            Op::Return => {
+                let result = self.pop();
+                let frame = self.frames.pop().expect("No frame to return from.");
+                self.close_upvalues(frame.base);
+                self.interpreter.stack.values.truncate(frame.base);
+                //The frame Rust called has no entry in the call stack, whoever called it owns that
+                if self.frames.is_empty() {
+                    return Ok(Some(result));
+                }
+                self.interpreter.truncate_call_stack(self.interpreter.call_stack().len() - 1);
+                self.push(result);
+            },
+            Op::Class(index) => {
+                let class = FxClass { name: chunk.name(index).to_string(), superclass: None, methods: HashMap::new() };
+                self.push(FxUnit::Class(Rc::new(RefCell::new(class))));
+            },
+            Op::Inherit => {
+                let subclass = self.pop();
+                match (self.peek(0), subclass) {
+                    (FxUnit::Class(superclass), FxUnit::Class(subclass)) => subclass.borrow_mut().superclass = Some(Rc::clone(superclass)),
+                    _ => return Err(runtime("Superclass must be a class."))
+                }
+            },
+            Op::Method(index) => {
+                let method = self.pop();
+                if let (FxUnit::Class(class), FxUnit::Callable(method)) = (self.peek(0), method) {
+                    class.borrow_mut().methods.insert(chunk.name(index).to_string(), method);
+                }
+            },
+            Op::List(count) => {
+                let at = self.interpreter.stack.values.len() - count as usize;
+                let elements = self.interpreter.stack.values.split_off(at);
+                self.push(FxUnit::list(elements));
+            },
+            Op::Map(count) => {
+                let at = self.interpreter.stack.values.len() - 2 * count as usize;
+                let flat = self.interpreter.stack.values.split_off(at);
+                let mut entries = BTreeMap::new();
+                let mut flat = flat.into_iter();
+                while let (Some(key), Some(value)) = (flat.next(), flat.next()) {
+                    entries.insert(self.interpreter.map_key(token(), &key)?, value);
+                }
+                self.push(FxUnit::map(entries));
+            },
+            Op::Throw => {
+                let value = self.pop();
+                return Err(Error::Throw { token: token().clone(), value: Box::new(value) });
+            },
+            Op::PushCatch(offset) | Op::PushFinally(offset) => {
+                let kind = if let Op::PushCatch(_) = chunk.code[ip] { HandlerKind::Catch } else { HandlerKind::Finally };
+                self.handlers.push(Handler {
+                    kind,
+                    target: ip + 1 + offset as usize,
+                    frames: self.frames.len(),
+                    stack: self.interpreter.stack.values.len(),
+                    call_stack: self.interpreter.call_stack().len(),
+                });
+            },
+            Op::PopHandler => {
+                self.handlers.pop();
+            },
+            Op::Rethrow => {
+                let (error, traceback) = self.pending.pop().expect("No error to rethrow.");
+                self.interpreter.restore_traceback(traceback);
+                return Err(error);
+            },
+            Op::DropPending => {
+                self.pending.pop();
+            },
+            Op::Import(index) => {
+                let module = self.interpreter.import_module(token(), chunk.name(index), run_module)?;
+                self.push(FxUnit::Module(module));
+            },
+            Op::Export(index) => self.interpreter.export(chunk.name(index)),
+            Op::Tick => self.interpreter.tick()?,
+        }
+        Ok(None)
+    }
+
+    //Calls the callee sitting below `count` arguments, compiled code gets a new frame
+    fn call_value(&mut self, count: usize, paren: &Token) -> Result<(), Error> {
+        let slot = self.interpreter.stack.values.len() - count - 1;
+        let callee = self.interpreter.stack.values[slot].clone();
+        let name = match &callee {
+            FxUnit::Callable(func) => func.name(),
+            FxUnit::Class(class) => class.borrow().name.clone(),
+            other => other.to_string()
+        };
+        if let Some(message) = self.interpreter.call_overflow(&name) {
+            return Err(Error::Runtime { token: paren.clone(), message });
+        }
+        let at_call = |error: Error| match error {
+            Error::Native { message } => Error::Runtime { token: paren.clone(), message },
+            other => other
+        };
+
+        let (closure, receiver) = match callee {
+            FxUnit::Callable(FxFx::Compiled { closure, this }) => (closure, this.map(|this| *this)),
+            FxUnit::Class(ref class) => {
+                let instance = FxClassInstance::new(class);
+                let init = class.borrow().find_method("init");
+                match init {
+                    Some(FxFx::Compiled { closure, .. }) => (closure, Some(instance)),
+                    Some(init) => {
+                        self.call_native(&init.bind(instance.clone()), slot, name, paren)?;
+                        self.pop();
+                        self.push(instance);
+                        return Ok(());
+                    },
+                    None => {
+                        self.interpreter.stack.values.truncate(slot);
+                        self.push(instance);
+                        return Ok(());
+                    }
+                }
+            },
+            FxUnit::Callable(func) => return self.call_native(&func, slot, name, paren),
+            _ => return Err(Error::Runtime { token: paren.clone(), message: "Can only call funcs and classes.".to_string() })
+        };
+
+        Interpreter::check_arity(Arity::Exact(closure.function.arity), count).map_err(at_call)?;
+        if let Some(receiver) = receiver {
+            self.interpreter.stack.values[slot] = receiver;
+        }
+        self.interpreter.push_frame(Frame { name, line: paren.line });
+        self.frames.push(CallFrame { closure, ip: 0, base: slot });
+        Ok(())
+    }
+
+    //Natives, and tree-walker functions from a module the tree-walker loaded, run in Rust
+    fn call_native(&mut self, func: &FxFx, slot: usize, name: String, paren: &Token) -> Result<(), Error> {
+        let args = self.interpreter.stack.values.split_off(slot + 1);
+        self.pop();
+        self.interpreter.push_frame(Frame { name, line: paren.line });
+        let result = Interpreter::check_arity(func.arity(), args.len())
+            .and_then(|_| func.call(self.interpreter, &args))
+            .map_err(|error| match error {
+                Error::Native { message } => Error::Runtime { token: paren.clone(), message },
+                other => other
+            });
+        if let Err(ref error) = result {
+            self.interpreter.note_traceback(error, Some(paren.line));
+        }
+        let depth = self.interpreter.call_stack().len() - 1;
+        self.interpreter.truncate_call_stack(depth);
+        self.push(result?);
+        Ok(())
+    }
+}
+
+fn is_truthy(value: &FxUnit) -> bool {
+    !matches!(value, FxUnit::Nil | FxUnit::Boolean(false))
+}
@@ -0,0 +1,10 @@
+/*
+    Bytecode backend. The compiler turns the resolved AST into chunks of Ops with a
+    constant pool, the machine runs them on a value stack with call frames and upvalues.
+    It shares values, natives, modules and errors with the tree-walking Interpreter.
+*/
+pub mod chunk;
+pub mod compiler;
+pub mod machine;
+
+pub use machine::run;
@@ -0,0 +1,76 @@
+/*
+    Golden-output tests for examples/. Each script runs with its printed output captured,
+    scripted input from tests/golden/NAME.in when present, and is compared against
+    tests/golden/NAME.out, once on each backend. Run with FXLANG_BLESS=1 to rewrite the
+    .out files from the tree-walker.
+*/
+use fxlang::{Backend, Engine};
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Cursor, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+//Output depends on the clock
+const SKIP: &[&str] = &["fib.fx"];
+
+#[derive(Clone, Default)]
+struct Captured(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn run_example(script: &Path, input: Vec<u8>, backend: Backend) -> String {
+    let output = Captured::default();
+    let mut engine = Engine::with_io(Box::new(output.clone()), Box::new(Cursor::new(input)));
+    engine.set_backend(backend);
+    let result = engine.eval_file(script);
+    let mut text = String::from_utf8(output.0.borrow().clone()).expect("output is not UTF-8");
+    if let Err(e) = result {
+        text.push_str(&engine.render(&e));
+    }
+    text
+}
+
+#[test]
+fn examples_match_golden_output() {
+    colored::control::set_override(false);
+    let bless = std::env::var_os("FXLANG_BLESS").is_some();
+    let golden = Path::new("tests/golden");
+    let mut scripts: Vec<_> = fs::read_dir("examples").unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "fx"))
+        .collect();
+    scripts.sort();
+
+    let mut failures = Vec::new();
+    for script in scripts {
+        let name = script.file_name().unwrap().to_str().unwrap().to_string();
+        if SKIP.contains(&name.as_str()) {
+            continue;
+        }
+        let stem = script.file_stem().unwrap().to_str().unwrap();
+        let input = fs::read(golden.join(format!("{}.in", stem))).unwrap_or_default();
+        let expected_path = golden.join(format!("{}.out", stem));
+        if bless {
+            fs::write(&expected_path, run_example(&script, input, Backend::TreeWalker)).unwrap();
+            continue;
+        }
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let actual = run_example(&script, input.clone(), backend);
+            match fs::read_to_string(&expected_path) {
+                Ok(expected) if expected == actual => (),
+                Ok(expected) => failures.push(format!("{} ({:?}):\n--- expected\n{}--- actual\n{}", name, backend, expected, actual)),
+                Err(_) => failures.push(format!("{}: missing {}", name, expected_path.display())),
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}